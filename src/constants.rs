@@ -3,6 +3,8 @@
 // memory
 pub const MEM_SIZE: u64 = 0x8000000;
 pub const MEM_BASE: u64 = 0x80000000;
+// largest pmem window or ram region, bounds each host allocation
+pub const MEM_SIZE_MAX: u64 = 0x100000000;
 pub const PC_RESET_OFFSET: u64 = 0x0;
 pub const MEM_LEFT: u64 = MEM_BASE;
 pub const MEM_RIGHT: u64 = MEM_BASE + MEM_SIZE - 1;
@@ -1,26 +1,71 @@
+pub mod disasm;
+mod history;
+pub mod icache;
 mod instruction;
 pub mod memory;
-mod utils;
+mod progress;
+pub mod retire;
+mod snapshot;
 mod statistic;
+mod utils;
 
+use crate::constants::{MEM_BASE, MEM_SIZE, RESET_VECTOR};
 use ansi_term::Colour::{Green, Red};
+use disasm::disassemble;
+use history::{History, HistoryEntry};
+use icache::{ICache, ICacheConfig};
+use instruction::InstPattern;
 use instruction::{
   BranchType, ImmediateType, Instruction, JumpType, RegisterType, StoreType,
   UpperType,
 };
-use memory::{read_data, read_inst, write_data};
+use memory::{
+  read_inst, try_read_data, try_write_data, AccessKind, MemAccess, MemRecord,
+};
+use progress::ProgressMonitor;
+use retire::{writes_rd, RetireRecord, TraceHash};
+use snapshot::Snapshot;
+use std::io::Write;
 use utils::{decode_operand, match_inst, sext};
-use instruction::InstPattern;
 
 #[derive(PartialEq)]
 pub enum CpuState {
   Running,
   // Stopped,
   Ended,
-  // Aborted,
+  Aborted,
   Quit,
 }
 
+// why a run stopped, mapped to the process exit status by main
+#[derive(Debug, PartialEq)]
+pub enum ExitReason {
+  // the guest hit the hemu trap with this code in a0
+  Halted(i32),
+  // invalid instruction or no forward progress
+  Aborted,
+  // the requested number of steps ran out while the guest was running
+  LimitReached,
+  UserQuit,
+  // bad arguments, img or config, the guest never ran
+  InitFailed,
+}
+
+impl ExitReason {
+  // the guest code for a halt, shell conventions for the rest; the OS
+  // keeps only the low byte, so a failing code like 256 must not read as 0
+  pub fn exit_code(&self) -> i32 {
+    match self {
+      ExitReason::Halted(code) if *code != 0 && *code & 0xff == 0 => 1,
+      ExitReason::Halted(code) => *code,
+      ExitReason::Aborted => 134,
+      ExitReason::LimitReached => 124,
+      ExitReason::UserQuit => 0,
+      ExitReason::InitFailed => 1,
+    }
+  }
+}
+
 pub struct Halt {
   pc: u32,
   ret: u32,
@@ -32,6 +77,29 @@ impl Halt {
   }
 }
 
+// machine state applied on construction and on every reset
+#[derive(Copy, Clone)]
+pub struct BootConfig {
+  pub reset_pc: u64,
+  // sp at reset, the top of the main pmem window
+  pub stack_top: u64,
+}
+
+impl BootConfig {
+  pub fn new() -> BootConfig {
+    BootConfig {
+      reset_pc: RESET_VECTOR,
+      stack_top: MEM_BASE + MEM_SIZE,
+    }
+  }
+
+  fn gpr(&self) -> [u64; 32] {
+    let mut gpr = [0; 32];
+    gpr[2] = self.stack_top;
+    gpr
+  }
+}
+
 pub struct Cpu {
   pub gpr: [u64; 32],
   pub pc: u64,
@@ -41,19 +109,213 @@ pub struct Cpu {
   pub state: CpuState,
   halt: Halt,
   statistic: statistic::Statistic,
+  history: History,
+  count_only: bool,
+  record: MemRecord,
+  last_access: Option<MemAccess>,
+  retire_trace: Option<Box<dyn Write>>,
+  trace_regs: bool,
+  progress: Option<ProgressMonitor>,
+  trace_hash: Option<TraceHash>,
+  icache: Option<ICache>,
+  boot: BootConfig,
 }
 
 impl Cpu {
+  #[allow(dead_code)]
   pub fn new() -> Cpu {
+    Cpu::with_boot_config(BootConfig::new())
+  }
+
+  pub fn with_boot_config(boot: BootConfig) -> Cpu {
     Cpu {
-      gpr: [0; 32],
-      pc: 0x80000000,
-      snpc: 0x80000000,
-      dnpc: 0x80000000,
+      gpr: boot.gpr(),
+      pc: boot.reset_pc,
+      snpc: boot.reset_pc,
+      dnpc: boot.reset_pc,
       inst: 0,
       state: CpuState::Running,
       halt: Halt::new(),
       statistic: statistic::Statistic::new(),
+      history: History::new(0),
+      count_only: false,
+      record: MemRecord::new(0),
+      last_access: None,
+      retire_trace: None,
+      trace_regs: false,
+      progress: None,
+      trace_hash: None,
+      icache: None,
+      boot,
+    }
+  }
+
+  pub fn exit_reason(&self) -> ExitReason {
+    match self.state {
+      CpuState::Running => ExitReason::LimitReached,
+      CpuState::Ended => ExitReason::Halted(self.halt.ret as i32),
+      CpuState::Aborted => ExitReason::Aborted,
+      CpuState::Quit => ExitReason::UserQuit,
+    }
+  }
+
+  // warm reset: put the architectural state back to what the boot config
+  // describes and forget the previous run, memory keeps its contents so
+  // the loaded img can run again
+  pub fn reset(&mut self) {
+    self.gpr = self.boot.gpr();
+    self.pc = self.boot.reset_pc;
+    self.snpc = self.boot.reset_pc;
+    self.dnpc = self.boot.reset_pc;
+    self.inst = 0;
+    self.state = CpuState::Running;
+    self.halt = Halt::new();
+    self.statistic = statistic::Statistic::new();
+    self.history.clear();
+    self.record.clear();
+    self.last_access = None;
+    if self.trace_hash.is_some() {
+      self.trace_hash = Some(TraceHash::new());
+    }
+    if let Some(progress) = self.progress.as_mut() {
+      progress.clear();
+    }
+    if let Some(icache) = self.icache.as_mut() {
+      icache.clear();
+    }
+  }
+
+  // write pc, registers and memory to `path` for loading into another hemu
+  pub fn save_state(
+    &self,
+    path: &std::path::Path,
+  ) -> Result<(), Box<dyn std::error::Error>> {
+    let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+    Snapshot::capture(self).write_to(&mut out)?;
+    out.flush()?;
+    Ok(())
+  }
+
+  // warm reset into the state saved by save_state
+  pub fn load_state(
+    &mut self,
+    path: &std::path::Path,
+  ) -> Result<(), Box<dyn std::error::Error>> {
+    let mut input = std::io::BufReader::new(std::fs::File::open(path)?);
+    Snapshot::read_from(&mut input)?.restore(self)
+  }
+
+  // record up to `depth` data accesses per exec call (0 disables recording)
+  pub fn set_record_depth(&mut self, depth: usize) {
+    self.record = MemRecord::new(depth);
+  }
+
+  pub fn mem_accesses(&self) -> impl Iterator<Item = &MemAccess> {
+    self.record.iter()
+  }
+
+  pub fn dump_mem_accesses(&self) {
+    for access in self.mem_accesses() {
+      println!("{}", access);
+    }
+  }
+
+  // like dump_mem_accesses, with adjacent stores combined into words
+  pub fn dump_coalesced_accesses(&self) {
+    for access in self.record.coalesced(8) {
+      println!("{}", access);
+    }
+  }
+
+  // None if the access aborted the run, the load must not write rd then
+  fn read(&mut self, addr: u64, len: i32) -> Option<u64> {
+    let Some(data) = try_read_data(addr, len) else {
+      self.bad_access(addr, len);
+      return None;
+    };
+    let access = MemAccess {
+      kind: AccessKind::Read,
+      addr,
+      len,
+      data,
+    };
+    self.record.push(access);
+    self.last_access = Some(access);
+    Some(data)
+  }
+
+  fn write(&mut self, addr: u64, len: i32, data: u64) {
+    if !try_write_data(addr, len, data) {
+      self.bad_access(addr, len);
+      return;
+    }
+    let data = data & (u64::MAX >> (64 - len * 8));
+    let access = MemAccess {
+      kind: AccessKind::Write,
+      addr,
+      len,
+      data,
+    };
+    self.record.push(access);
+    self.last_access = Some(access);
+  }
+
+  // skip all per-instruction tracing, only the statistic counters are kept
+  pub fn set_count_only(&mut self, count_only: bool) {
+    self.count_only = count_only;
+  }
+
+  // write a RetireRecord line for every traced step
+  pub fn set_retire_trace(&mut self, trace: Box<dyn Write>) {
+    self.retire_trace = Some(trace);
+  }
+
+  // follow every retire trace line with the whole register file
+  pub fn set_trace_regs(&mut self, enable: bool) {
+    self.trace_regs = enable;
+  }
+
+  // abort once a pc retires `limit` times without any register or memory
+  // change in between (0 disables the check)
+  pub fn set_progress_limit(&mut self, limit: u64) {
+    self.progress = (limit > 0).then(|| ProgressMonitor::new(limit));
+  }
+
+  // fold every traced retirement into a hash, count-only runs leave it untouched
+  pub fn set_trace_hash(&mut self, enable: bool) {
+    self.trace_hash = enable.then(TraceHash::new);
+  }
+
+  pub fn trace_hash(&self) -> Option<u64> {
+    self.trace_hash.as_ref().map(TraceHash::value)
+  }
+
+  // run every fetch through a modeled icache (None disables the model)
+  pub fn set_icache(&mut self, config: Option<ICacheConfig>) {
+    self.icache = config.map(ICache::new);
+  }
+
+  // (hits, misses) of the modeled icache
+  #[allow(dead_code)]
+  pub fn icache_stats(&self) -> Option<(u64, u64)> {
+    self
+      .icache
+      .as_ref()
+      .map(|icache| (icache.hits, icache.misses))
+  }
+
+  // keep the last `size` retired instructions for post-mortem dumps
+  pub fn set_history_size(&mut self, size: usize) {
+    self.history = History::new(size);
+  }
+
+  pub fn recent_history(&self) -> impl Iterator<Item = &HistoryEntry> {
+    self.history.iter()
+  }
+
+  fn dump_history(&self) {
+    for entry in self.recent_history() {
+      log::error!("{}", entry);
     }
   }
 
@@ -62,19 +324,460 @@ impl Cpu {
     self.halt.pc = self.pc as u32;
     self.halt.ret = self.gpr[10] as u32;
 
-    log::info!("hemu trap, pc = {:x}, ret = {}", self.pc, self.gpr[10]);
+    log::info!(pc = self.pc, ret = self.gpr[10]; "hemu trap, pc = {:x}, ret = {}", self.pc, self.gpr[10]);
   }
 
-  pub fn fetch(&mut self) {
-    self.inst = read_inst(self.pc) as u32;
-    self.snpc += 4;
+  // unimp, unknown encodings and ecall stop the run instead of executing anything
+  fn invalid_inst(&mut self) {
+    self.state = CpuState::Aborted;
+    self.halt.pc = self.pc as u32;
+    self.dnpc = self.pc;
 
-    log::debug!("fetch: pc = 0x{:08x}, inst = 0x{:08x}", self.pc, self.inst);
+    log::error!(
+      pc = self.pc, inst = self.inst;
+      "invalid instruction at pc = 0x{:08x}: 0x{:08x} ({})", self.pc, self.inst, disassemble(self.pc, self.inst)
+    );
   }
 
-  pub fn decode(&self, inst_type: &mut Instruction) {
-    #[rustfmt::skip]
-    let patterns = [
+  fn no_progress(&mut self, pc: u64) {
+    self.state = CpuState::Aborted;
+    self.halt.pc = pc as u32;
+
+    log::error!(pc = pc; "no forward progress at pc 0x{:08x}", pc);
+  }
+
+  // an access no memory region backs stops the run like any other abort,
+  // instead of a host panic that loses the history
+  fn bad_access(&mut self, addr: u64, len: i32) {
+    self.state = CpuState::Aborted;
+    self.halt.pc = self.pc as u32;
+    self.dnpc = self.pc;
+
+    log::error!(
+      pc = self.pc, addr = addr;
+      "address 0x{:016x} [{}] is out of bound of memory at pc 0x{:08x}", addr, len, self.pc
+    );
+  }
+
+  // the static next pc always follows the fetched instruction, control
+  // flow only ever overrides dnpc with an absolute target, returns false
+  // if no memory backs the pc
+  pub fn fetch(&mut self) -> bool {
+    self.snpc = self.pc.wrapping_add(4);
+    if let Some(icache) = self.icache.as_mut() {
+      icache.access(self.pc);
+    }
+    match read_inst(self.pc) {
+      Some(inst) => {
+        self.inst = inst as u32;
+        true
+      }
+      None => {
+        self.inst = 0;
+        self.bad_access(self.pc, 4);
+        false
+      }
+    }
+  }
+
+  // returns false if the instruction matches no pattern
+  pub fn decode(&self, inst_type: &mut Instruction) -> bool {
+    match decode_inst(self.inst) {
+      Some(itype) => {
+        *inst_type = itype;
+        true
+      }
+      None => false,
+    }
+  }
+
+  // returns the rd the instruction wrote, if any
+  #[rustfmt::skip]
+  pub fn execute(&mut self, inst_type: Instruction) -> Option<usize> {
+    let (rd, rs1, rs2, imm) = decode_operand(self.inst, inst_type);
+    self.dnpc = self.snpc;
+    match inst_type {
+      Instruction::Register(RegisterType::ADD)  => {self.gpr[rd] = self.gpr[rs1].wrapping_add(self.gpr[rs2]);}
+      Instruction::Register(RegisterType::SUB)  => {self.gpr[rd] = self.gpr[rs1].wrapping_sub(self.gpr[rs2]);}
+      Instruction::Register(RegisterType::XOR)  => {self.gpr[rd] = self.gpr[rs1] ^ self.gpr[rs2];}
+      Instruction::Register(RegisterType::OR)   => {self.gpr[rd] = self.gpr[rs1] | self.gpr[rs2];}
+      Instruction::Register(RegisterType::AND)  => {self.gpr[rd] = self.gpr[rs1] & self.gpr[rs2];}
+      Instruction::Register(RegisterType::SLL)  => {self.gpr[rd] = self.gpr[rs1] << (self.gpr[rs2] & 0x3f);}
+      Instruction::Register(RegisterType::SRL)  => {self.gpr[rd] = self.gpr[rs1] >> (self.gpr[rs2] & 0x3f);}
+      Instruction::Register(RegisterType::SRA)  => {self.gpr[rd] = (self.gpr[rs1] as i64 >> (self.gpr[rs2] & 0x3f)) as u64;}
+      Instruction::Register(RegisterType::SLT)  => {self.gpr[rd] = if (self.gpr[rs1] as i64) < (self.gpr[rs2] as i64) {1} else {0};}
+      Instruction::Register(RegisterType::SLTU) => {self.gpr[rd] = if self.gpr[rs1] < self.gpr[rs2] {1} else {0};}
+
+      Instruction::Immediate(ImmediateType::ADDI)  => {self.gpr[rd] = self.gpr[rs1].wrapping_add(imm as u64);}
+      Instruction::Immediate(ImmediateType::XORI)  => {self.gpr[rd] = self.gpr[rs1] ^ imm as u64;}
+      Instruction::Immediate(ImmediateType::ORI)   => {self.gpr[rd] = self.gpr[rs1] | imm as u64;}
+      Instruction::Immediate(ImmediateType::ANDI)  => {self.gpr[rd] = self.gpr[rs1] & imm as u64;}
+      Instruction::Immediate(ImmediateType::SLLI)  => {self.gpr[rd] = self.gpr[rs1] << (imm & 0x3f);}
+      Instruction::Immediate(ImmediateType::SRLI)  => {self.gpr[rd] = self.gpr[rs1] >> (imm & 0x3f);}
+      Instruction::Immediate(ImmediateType::SRAI)  => {self.gpr[rd] = (self.gpr[rs1] as i64 >> (imm & 0x3f)) as u64;}
+      Instruction::Immediate(ImmediateType::SLTI)  => {self.gpr[rd] = if (self.gpr[rs1] as i64) < imm {1} else {0};}
+      Instruction::Immediate(ImmediateType::SLTIU) => {self.gpr[rd] = if self.gpr[rs1] < imm as u64 {1} else {0};}
+
+      Instruction::Register(RegisterType::SLLW)     => {self.gpr[rd] = ((self.gpr[rs1] as u32) << (self.gpr[rs2] & 0x1f)) as i32 as i64 as u64;}
+      Instruction::Register(RegisterType::SRLW)     => {self.gpr[rd] = ((self.gpr[rs1] as u32) >> (self.gpr[rs2] & 0x1f)) as i32 as i64 as u64;}
+      Instruction::Register(RegisterType::SRAW)     => {self.gpr[rd] = ((self.gpr[rs1] as i32) >> (self.gpr[rs2] & 0x1f)) as i64 as u64;}
+      Instruction::Immediate(ImmediateType::SLLIW)  => {self.gpr[rd] = ((self.gpr[rs1] as u32) << (imm & 0x1f)) as i32 as i64 as u64;}
+      Instruction::Immediate(ImmediateType::SRLIW)  => {self.gpr[rd] = ((self.gpr[rs1] as u32) >> (imm & 0x1f)) as i32 as i64 as u64;}
+      Instruction::Immediate(ImmediateType::SRAIW)  => {self.gpr[rd] = ((self.gpr[rs1] as i32) >> (imm & 0x1f)) as i64 as u64;}
+
+      Instruction::Immediate(ImmediateType::LB)  => {if let Some(data) = self.read(self.gpr[rs1].wrapping_add(imm as u64), 1) {self.gpr[rd] = sext(data as usize, 8) as u64;}}
+      Instruction::Immediate(ImmediateType::LBU) => {if let Some(data) = self.read(self.gpr[rs1].wrapping_add(imm as u64), 1) {self.gpr[rd] = data;}}
+      Instruction::Immediate(ImmediateType::LH)  => {if let Some(data) = self.read(self.gpr[rs1].wrapping_add(imm as u64), 2) {self.gpr[rd] = sext(data as usize, 16) as u64;}}
+      Instruction::Immediate(ImmediateType::LHU) => {if let Some(data) = self.read(self.gpr[rs1].wrapping_add(imm as u64), 2) {self.gpr[rd] = data;}}
+      Instruction::Immediate(ImmediateType::LW)  => {if let Some(data) = self.read(self.gpr[rs1].wrapping_add(imm as u64), 4) {self.gpr[rd] = sext(data as usize, 32) as u64;}}
+      Instruction::Immediate(ImmediateType::LWU) => {if let Some(data) = self.read(self.gpr[rs1].wrapping_add(imm as u64), 4) {self.gpr[rd] = data;}}
+      Instruction::Immediate(ImmediateType::LD)  => {if let Some(data) = self.read(self.gpr[rs1].wrapping_add(imm as u64), 8) {self.gpr[rd] = data;}}
+
+      Instruction::Store(StoreType::SB) => {self.write(self.gpr[rs1].wrapping_add(imm as u64), 1, self.gpr[rs2]);}
+      Instruction::Store(StoreType::SH) => {self.write(self.gpr[rs1].wrapping_add(imm as u64), 2, self.gpr[rs2]);}
+      Instruction::Store(StoreType::SW) => {self.write(self.gpr[rs1].wrapping_add(imm as u64), 4, self.gpr[rs2]);}
+      Instruction::Store(StoreType::SD) => {self.write(self.gpr[rs1].wrapping_add(imm as u64), 8, self.gpr[rs2]);}
+
+      Instruction::Branch(BranchType::BEQ)  => {if self.gpr[rs1] == self.gpr[rs2] {self.dnpc = self.pc.wrapping_add(imm as u64);}}
+      Instruction::Branch(BranchType::BNE)  => {if self.gpr[rs1] != self.gpr[rs2] {self.dnpc = self.pc.wrapping_add(imm as u64);}}
+      Instruction::Branch(BranchType::BLT)  => {if (self.gpr[rs1] as i64) < (self.gpr[rs2] as i64) {self.dnpc = self.pc.wrapping_add(imm as u64);}}
+      Instruction::Branch(BranchType::BGE)  => {if (self.gpr[rs1] as i64) >= (self.gpr[rs2] as i64) {self.dnpc = self.pc.wrapping_add(imm as u64);}}
+      Instruction::Branch(BranchType::BLTU) => {if self.gpr[rs1] < self.gpr[rs2] {self.dnpc = self.pc.wrapping_add(imm as u64);}}
+      Instruction::Branch(BranchType::BGEU) => {if self.gpr[rs1] >= self.gpr[rs2] {self.dnpc = self.pc.wrapping_add(imm as u64);}}
+
+      Instruction::Jump(JumpType::JAL)            => {self.dnpc = self.pc.wrapping_add(imm as u64); self.gpr[rd] = self.snpc;}
+      Instruction::Immediate(ImmediateType::JALR) => {self.dnpc = self.gpr[rs1].wrapping_add(imm as u64) & !1; self.gpr[rd] = self.snpc;}
+
+      Instruction::Upper(UpperType::LUI)   => {self.gpr[rd] = imm as u64;}
+      Instruction::Upper(UpperType::AUIPC) => {self.gpr[rd] = self.pc.wrapping_add(imm as u64);}
+
+      #[cfg(feature = "m")] Instruction::Register(RegisterType::MUL)    => {self.gpr[rd] = self.gpr[rs1].wrapping_mul(self.gpr[rs2]);}
+      #[cfg(feature = "m")] Instruction::Register(RegisterType::MULH)   => {self.gpr[rd] = ((self.gpr[rs1] as i64 as i128).wrapping_mul(self.gpr[rs2] as i64 as i128) >> 64) as u64;}
+      #[cfg(feature = "m")] Instruction::Register(RegisterType::MULHSU) => {self.gpr[rd] = ((self.gpr[rs1] as i64 as i128).wrapping_mul(self.gpr[rs2] as i128) >> 64) as u64;}
+      #[cfg(feature = "m")] Instruction::Register(RegisterType::MULHU)  => {self.gpr[rd] = ((self.gpr[rs1] as u128).wrapping_mul(self.gpr[rs2] as u128) >> 64) as u64;}
+      #[cfg(feature = "m")] Instruction::Register(RegisterType::MULW)   => {self.gpr[rd] = (self.gpr[rs1] as i32).wrapping_mul(self.gpr[rs2] as i32) as i64 as u64;}
+
+      // division by zero and overflow do not trap, see the M extension spec
+      #[cfg(feature = "m")] Instruction::Register(RegisterType::DIV)   => {self.gpr[rd] = if self.gpr[rs2] == 0 {u64::MAX} else {(self.gpr[rs1] as i64).wrapping_div(self.gpr[rs2] as i64) as u64};}
+      #[cfg(feature = "m")] Instruction::Register(RegisterType::DIVU)  => {self.gpr[rd] = self.gpr[rs1].checked_div(self.gpr[rs2]).unwrap_or(u64::MAX);}
+      #[cfg(feature = "m")] Instruction::Register(RegisterType::REM)   => {self.gpr[rd] = if self.gpr[rs2] == 0 {self.gpr[rs1]} else {(self.gpr[rs1] as i64).wrapping_rem(self.gpr[rs2] as i64) as u64};}
+      #[cfg(feature = "m")] Instruction::Register(RegisterType::REMU)  => {self.gpr[rd] = self.gpr[rs1].checked_rem(self.gpr[rs2]).unwrap_or(self.gpr[rs1]);}
+      #[cfg(feature = "m")] Instruction::Register(RegisterType::DIVW)  => {self.gpr[rd] = if self.gpr[rs2] as i32 == 0 {u64::MAX} else {(self.gpr[rs1] as i32).wrapping_div(self.gpr[rs2] as i32) as i64 as u64};}
+      #[cfg(feature = "m")] Instruction::Register(RegisterType::DIVUW) => {self.gpr[rd] = (self.gpr[rs1] as u32).checked_div(self.gpr[rs2] as u32).unwrap_or(u32::MAX) as i32 as i64 as u64;}
+      #[cfg(feature = "m")] Instruction::Register(RegisterType::REMW)  => {self.gpr[rd] = if self.gpr[rs2] as i32 == 0 {self.gpr[rs1] as i32 as i64 as u64} else {(self.gpr[rs1] as i32).wrapping_rem(self.gpr[rs2] as i32) as i64 as u64};}
+      #[cfg(feature = "m")] Instruction::Register(RegisterType::REMUW) => {self.gpr[rd] = (self.gpr[rs1] as u32).checked_rem(self.gpr[rs2] as u32).unwrap_or(self.gpr[rs1] as u32) as i32 as i64 as u64;}
+
+      #[cfg(feature = "zbs")] Instruction::Register(RegisterType::BCLR) => {self.gpr[rd] = self.gpr[rs1] & !(1 << (self.gpr[rs2] & 0x3f));}
+      #[cfg(feature = "zbs")] Instruction::Register(RegisterType::BEXT) => {self.gpr[rd] = (self.gpr[rs1] >> (self.gpr[rs2] & 0x3f)) & 1;}
+      #[cfg(feature = "zbs")] Instruction::Register(RegisterType::BINV) => {self.gpr[rd] = self.gpr[rs1] ^ (1 << (self.gpr[rs2] & 0x3f));}
+      #[cfg(feature = "zbs")] Instruction::Register(RegisterType::BSET) => {self.gpr[rd] = self.gpr[rs1] | (1 << (self.gpr[rs2] & 0x3f));}
+
+      #[cfg(feature = "zbs")] Instruction::Immediate(ImmediateType::BCLRI) => {self.gpr[rd] = self.gpr[rs1] & !(1 << (imm & 0x3f));}
+      #[cfg(feature = "zbs")] Instruction::Immediate(ImmediateType::BEXTI) => {self.gpr[rd] = (self.gpr[rs1] >> (imm & 0x3f)) & 1;}
+      #[cfg(feature = "zbs")] Instruction::Immediate(ImmediateType::BINVI) => {self.gpr[rd] = self.gpr[rs1] ^ (1 << (imm & 0x3f));}
+      #[cfg(feature = "zbs")] Instruction::Immediate(ImmediateType::BSETI) => {self.gpr[rd] = self.gpr[rs1] | (1 << (imm & 0x3f));}
+
+      // there are no traps to deliver an ecall through, abort like unimp
+      Instruction::Immediate(ImmediateType::ECALL)  => {self.invalid_inst();}
+      Instruction::Immediate(ImmediateType::EBREAK) => {self.hemu_trap();}
+
+    }
+    self.gpr[0] = 0;
+    writes_rd(inst_type, rd).then_some(rd)
+  }
+
+  // TRACE = false is the count-only path: per-instruction logging and
+  // history are compiled out rather than filtered at runtime
+  fn exec_once<const TRACE: bool>(&mut self) -> Option<usize> {
+    // pipeline start
+    let pc = self.pc;
+    self.last_access = None;
+    let mut inst_type = Instruction::Immediate(ImmediateType::EBREAK);
+    // fetch stage
+    let fetched = self.fetch();
+    if TRACE {
+      log::debug!(pc = self.pc, inst = self.inst; "fetch: pc = 0x{:08x}, inst = 0x{:08x}", self.pc, self.inst);
+    }
+    // decode stage
+    let valid = fetched && self.decode(&mut inst_type);
+    // execute stage (including memory stage and write back stage)
+    let mut rd = None;
+    if valid {
+      rd = self.execute(inst_type);
+    } else if fetched {
+      self.invalid_inst();
+    }
+    if TRACE {
+      self.history.push(HistoryEntry {
+        pc: self.pc,
+        inst: self.inst,
+        fetch_fault: !fetched,
+      });
+    }
+    // update pc
+    self.pc = self.dnpc;
+    if TRACE && self.observes_retire() {
+      let record = self.retire_record(pc, rd);
+      if let Some(hash) = self.trace_hash.as_mut() {
+        hash.update(&record);
+      }
+      if let Some(trace) = self.retire_trace.as_mut() {
+        writeln!(trace, "{}", record).expect("failed to write retire trace");
+        if self.trace_regs {
+          write!(trace, "{}", format_registers(&self.gpr))
+            .expect("failed to write retire trace");
+        }
+      }
+      if self
+        .progress
+        .as_mut()
+        .is_some_and(|progress| progress.stalled(&record))
+      {
+        self.no_progress(pc);
+      }
+    }
+    rd
+  }
+
+  fn exec_ntimes<const TRACE: bool>(&mut self, n: usize) {
+    for _ in 0..n {
+      self.exec_once::<TRACE>();
+      self.statistic.inc_count();
+      if self.state != CpuState::Running {
+        break;
+      }
+    }
+  }
+
+  // execute one traced instruction and report what it retired
+  pub fn step(&mut self) -> RetireRecord {
+    let pc = self.pc;
+    let rd = self.exec_once::<true>();
+    self.statistic.inc_count();
+    self.retire_record(pc, rd)
+  }
+
+  // some consumer needs a RetireRecord for every step
+  fn observes_retire(&self) -> bool {
+    self.retire_trace.is_some()
+      || self.progress.is_some()
+      || self.trace_hash.is_some()
+  }
+
+  // what the instruction just executed from `pc` retired, `rd` is the
+  // register execute reported writing
+  fn retire_record(&self, pc: u64, rd: Option<usize>) -> RetireRecord {
+    RetireRecord {
+      pc,
+      next_pc: self.pc,
+      inst: self.inst,
+      rd: rd.map(|rd| (rd, self.gpr[rd])),
+      mem: self.last_access,
+    }
+  }
+
+  // single-step until `pred` holds, returning the number of steps taken
+  pub fn step_until(
+    &mut self,
+    pred: impl Fn(&Cpu) -> bool,
+    max: u64,
+  ) -> Result<u64, Box<dyn std::error::Error>> {
+    let mut steps = 0;
+    while !pred(self) {
+      if steps == max {
+        return Err(
+          format!("predicate not reached within {} steps", max).into(),
+        );
+      }
+      if self.state != CpuState::Running {
+        return Err(
+          format!(
+            "cpu stopped at pc = 0x{:08x} after {} steps",
+            self.pc, steps
+          )
+          .into(),
+        );
+      }
+      if self.count_only {
+        self.exec_ntimes::<false>(1);
+      } else {
+        self.step();
+      }
+      steps += 1;
+    }
+    Ok(steps)
+  }
+
+  fn statistic(&self) {
+    log::info!("host time spend = {:?}", self.statistic.time);
+    log::info!("total guest instructions = {:?}", self.statistic.count);
+    log::info!(
+      "simulation frequency = {:?}",
+      (self.statistic.count) as f64 / (self.statistic.time.as_secs_f64())
+    );
+    if let Some(icache) = &self.icache {
+      log::info!("icache hits = {}, misses = {}", icache.hits, icache.misses);
+    }
+  }
+
+  pub fn exec(&mut self, n: usize) -> i32 {
+    let start_time = self.statistic.start_timer();
+    self.record.clear();
+
+    if self.count_only {
+      self.exec_ntimes::<false>(n);
+    } else {
+      self.exec_ntimes::<true>(n);
+    }
+
+    self.statistic.stop_timer(start_time);
+
+    match self.state {
+      CpuState::Ended => {
+        if self.halt.ret == 0 {
+          println!("{}", Green.bold().paint("HIT GOOD TRAP"));
+          self.statistic();
+          return 0;
+        } else {
+          log::error!("{}", Red.bold().paint("HIT BAD TRAP"));
+          self.dump_history();
+          return -1;
+        }
+      }
+      CpuState::Aborted => {
+        log::error!("{}", Red.bold().paint("ABORT"));
+        self.dump_history();
+        return -1;
+      }
+      CpuState::Running => {}
+      CpuState::Quit => {
+        self.statistic();
+      }
+    }
+    0
+  }
+
+  pub fn dump_registers(&self) {
+    println!("{}", format_registers(&self.gpr));
+  }
+
+  pub fn dump_watches(&self) {
+    todo!("watch points not implemented!");
+  }
+}
+
+// four registers per line, every line newline terminated
+fn format_registers(gpr: &[u64; 32]) -> String {
+  let mut out = String::new();
+  for (i, value) in gpr.iter().enumerate() {
+    out += &format!("x{:02} = 0x{:016x} ", i, value);
+    if i % 4 == 3 {
+      out += "\n";
+    }
+  }
+  out
+}
+
+pub fn decode_inst(inst: u32) -> Option<Instruction> {
+  decode_base(inst).or_else(|| decode_table(inst))
+}
+
+// hand-written decode of the hot RV64I opcodes; None leaves the
+// instruction to the pattern table, which stays the reference
+#[rustfmt::skip]
+fn decode_base(inst: u32) -> Option<Instruction> {
+  let funct3 = (inst >> 12) & 0x7;
+  let funct6 = inst >> 26;
+  let funct7 = inst >> 25;
+  let itype = match (inst & 0x7f, funct3) {
+    (0b0110011, _) => Instruction::Register(match (funct7, funct3) {
+      (0b0000000, 0b000) => RegisterType::ADD,
+      (0b0100000, 0b000) => RegisterType::SUB,
+      (0b0000000, 0b001) => RegisterType::SLL,
+      (0b0000000, 0b010) => RegisterType::SLT,
+      (0b0000000, 0b011) => RegisterType::SLTU,
+      (0b0000000, 0b100) => RegisterType::XOR,
+      (0b0000000, 0b101) => RegisterType::SRL,
+      (0b0100000, 0b101) => RegisterType::SRA,
+      (0b0000000, 0b110) => RegisterType::OR,
+      (0b0000000, 0b111) => RegisterType::AND,
+      _ => return None,
+    }),
+    (0b0010011, _) => Instruction::Immediate(match funct3 {
+      0b000 => ImmediateType::ADDI,
+      0b001 if funct6 == 0b000000 => ImmediateType::SLLI,
+      0b010 => ImmediateType::SLTI,
+      0b011 => ImmediateType::SLTIU,
+      0b100 => ImmediateType::XORI,
+      0b101 if funct6 == 0b000000 => ImmediateType::SRLI,
+      0b101 if funct6 == 0b010000 => ImmediateType::SRAI,
+      0b110 => ImmediateType::ORI,
+      0b111 => ImmediateType::ANDI,
+      _ => return None,
+    }),
+    (0b0000011, _) => Instruction::Immediate(match funct3 {
+      0b000 => ImmediateType::LB,
+      0b001 => ImmediateType::LH,
+      0b010 => ImmediateType::LW,
+      0b011 => ImmediateType::LD,
+      0b100 => ImmediateType::LBU,
+      0b101 => ImmediateType::LHU,
+      0b110 => ImmediateType::LWU,
+      _ => return None,
+    }),
+    (0b0100011, _) => Instruction::Store(match funct3 {
+      0b000 => StoreType::SB,
+      0b001 => StoreType::SH,
+      0b010 => StoreType::SW,
+      0b011 => StoreType::SD,
+      _ => return None,
+    }),
+    (0b1100011, _) => Instruction::Branch(match funct3 {
+      0b000 => BranchType::BEQ,
+      0b001 => BranchType::BNE,
+      0b100 => BranchType::BLT,
+      0b101 => BranchType::BGE,
+      0b110 => BranchType::BLTU,
+      0b111 => BranchType::BGEU,
+      _ => return None,
+    }),
+    (0b1101111, _)     => Instruction::Jump(JumpType::JAL),
+    (0b1100111, 0b000) => Instruction::Immediate(ImmediateType::JALR),
+    (0b0110111, _)     => Instruction::Upper(UpperType::LUI),
+    (0b0010111, _)     => Instruction::Upper(UpperType::AUIPC),
+    _ => return None,
+  };
+  Some(itype)
+}
+
+fn decode_table(inst: u32) -> Option<Instruction> {
+  PATTERNS
+    .iter()
+    .find(|pattern| match_inst(inst, pattern.pattern))
+    .map(|pattern| pattern.itype)
+}
+
+// (mnemonic, pattern, category) of every decodable instruction, in table order
+pub fn isa_table() -> Vec<(String, &'static str, &'static str)> {
+  PATTERNS
+    .iter()
+    .map(|pattern| {
+      let category = match pattern.itype {
+        Instruction::Register(_) => "Register",
+        Instruction::Immediate(_) => "Immediate",
+        Instruction::Store(_) => "Store",
+        Instruction::Branch(_) => "Branch",
+        Instruction::Jump(_) => "Jump",
+        Instruction::Upper(_) => "Upper",
+      };
+      (disasm::mnemonic(pattern.itype), pattern.pattern, category)
+    })
+    .collect()
+}
+
+// the decode reference, first match wins
+#[rustfmt::skip]
+static PATTERNS: &[InstPattern] = &[
     // Register 
   InstPattern::new("0000000 ????? ????? 000 ????? 01100 11", Instruction::Register(RegisterType::ADD)),
   InstPattern::new("0100000 ????? ????? 000 ????? 01100 11", Instruction::Register(RegisterType::SUB)),
@@ -83,6 +786,7 @@ impl Cpu {
   InstPattern::new("0000000 ????? ????? 111 ????? 01100 11", Instruction::Register(RegisterType::AND)),
   InstPattern::new("0000000 ????? ????? 001 ????? 01100 11", Instruction::Register(RegisterType::SLL)),
   InstPattern::new("0000000 ????? ????? 101 ????? 01100 11", Instruction::Register(RegisterType::SRL)),
+  InstPattern::new("0100000 ????? ????? 101 ????? 01100 11", Instruction::Register(RegisterType::SRA)),
   InstPattern::new("0000000 ????? ????? 010 ????? 01100 11", Instruction::Register(RegisterType::SLT)),
   InstPattern::new("0000000 ????? ????? 011 ????? 01100 11", Instruction::Register(RegisterType::SLTU)),
     // Immediate
@@ -102,8 +806,14 @@ impl Cpu {
   InstPattern::new("??????? ????? ????? 010 ????? 00000 11", Instruction::Immediate(ImmediateType::LW)),
   InstPattern::new("??????? ????? ????? 110 ????? 00000 11", Instruction::Immediate(ImmediateType::LWU)),
   InstPattern::new("??????? ????? ????? 011 ????? 00000 11", Instruction::Immediate(ImmediateType::LD)),
-  InstPattern::new("??????? ????? ????? 111 ????? 00000 11", Instruction::Immediate(ImmediateType::LDU)),
   InstPattern::new("??????? ????? ????? 000 ????? 11001 11", Instruction::Immediate(ImmediateType::JALR)),
+    // RV64I word shifts
+  InstPattern::new("0000000 ????? ????? 001 ????? 01110 11", Instruction::Register(RegisterType::SLLW)),
+  InstPattern::new("0000000 ????? ????? 101 ????? 01110 11", Instruction::Register(RegisterType::SRLW)),
+  InstPattern::new("0100000 ????? ????? 101 ????? 01110 11", Instruction::Register(RegisterType::SRAW)),
+  InstPattern::new("0000000 ????? ????? 001 ????? 00110 11", Instruction::Immediate(ImmediateType::SLLIW)),
+  InstPattern::new("0000000 ????? ????? 101 ????? 00110 11", Instruction::Immediate(ImmediateType::SRLIW)),
+  InstPattern::new("0100000 ????? ????? 101 ????? 00110 11", Instruction::Immediate(ImmediateType::SRAIW)),
     // Store
   InstPattern::new("??????? ????? ????? 000 ????? 01000 11", Instruction::Store(StoreType::SB)),
   InstPattern::new("??????? ????? ????? 001 ????? 01000 11", Instruction::Store(StoreType::SH)),
@@ -122,12 +832,28 @@ impl Cpu {
   InstPattern::new("??????? ????? ????? ??? ????? 01101 11", Instruction::Upper(UpperType::LUI)),
   InstPattern::new("??????? ????? ????? ??? ????? 00101 11", Instruction::Upper(UpperType::AUIPC)),
     // RV32M
-  InstPattern::new("0000001 ????? ????? 000 ????? 01100 11", Instruction::Register(RegisterType::MUL)),
-  InstPattern::new("0000001 ????? ????? 000 ????? 01110 11", Instruction::Register(RegisterType::MULW)),
-  InstPattern::new("0000001 ????? ????? 100 ????? 01100 11", Instruction::Register(RegisterType::DIV)),
-  InstPattern::new("0000001 ????? ????? 101 ????? 01100 11", Instruction::Register(RegisterType::DIVU)),
-  InstPattern::new("0000001 ????? ????? 110 ????? 01100 11", Instruction::Register(RegisterType::REM)),
-  InstPattern::new("0000001 ????? ????? 111 ????? 01100 11", Instruction::Register(RegisterType::REMU)),
+  #[cfg(feature = "m")] InstPattern::new("0000001 ????? ????? 000 ????? 01100 11", Instruction::Register(RegisterType::MUL)),
+  #[cfg(feature = "m")] InstPattern::new("0000001 ????? ????? 001 ????? 01100 11", Instruction::Register(RegisterType::MULH)),
+  #[cfg(feature = "m")] InstPattern::new("0000001 ????? ????? 010 ????? 01100 11", Instruction::Register(RegisterType::MULHSU)),
+  #[cfg(feature = "m")] InstPattern::new("0000001 ????? ????? 011 ????? 01100 11", Instruction::Register(RegisterType::MULHU)),
+  #[cfg(feature = "m")] InstPattern::new("0000001 ????? ????? 000 ????? 01110 11", Instruction::Register(RegisterType::MULW)),
+  #[cfg(feature = "m")] InstPattern::new("0000001 ????? ????? 100 ????? 01100 11", Instruction::Register(RegisterType::DIV)),
+  #[cfg(feature = "m")] InstPattern::new("0000001 ????? ????? 101 ????? 01100 11", Instruction::Register(RegisterType::DIVU)),
+  #[cfg(feature = "m")] InstPattern::new("0000001 ????? ????? 110 ????? 01100 11", Instruction::Register(RegisterType::REM)),
+  #[cfg(feature = "m")] InstPattern::new("0000001 ????? ????? 111 ????? 01100 11", Instruction::Register(RegisterType::REMU)),
+  #[cfg(feature = "m")] InstPattern::new("0000001 ????? ????? 100 ????? 01110 11", Instruction::Register(RegisterType::DIVW)),
+  #[cfg(feature = "m")] InstPattern::new("0000001 ????? ????? 101 ????? 01110 11", Instruction::Register(RegisterType::DIVUW)),
+  #[cfg(feature = "m")] InstPattern::new("0000001 ????? ????? 110 ????? 01110 11", Instruction::Register(RegisterType::REMW)),
+  #[cfg(feature = "m")] InstPattern::new("0000001 ????? ????? 111 ????? 01110 11", Instruction::Register(RegisterType::REMUW)),
+    // Zbs
+  #[cfg(feature = "zbs")] InstPattern::new("0100100 ????? ????? 001 ????? 01100 11", Instruction::Register(RegisterType::BCLR)),
+  #[cfg(feature = "zbs")] InstPattern::new("0100100 ????? ????? 101 ????? 01100 11", Instruction::Register(RegisterType::BEXT)),
+  #[cfg(feature = "zbs")] InstPattern::new("0110100 ????? ????? 001 ????? 01100 11", Instruction::Register(RegisterType::BINV)),
+  #[cfg(feature = "zbs")] InstPattern::new("0010100 ????? ????? 001 ????? 01100 11", Instruction::Register(RegisterType::BSET)),
+  #[cfg(feature = "zbs")] InstPattern::new("010010? ????? ????? 001 ????? 00100 11", Instruction::Immediate(ImmediateType::BCLRI)),
+  #[cfg(feature = "zbs")] InstPattern::new("010010? ????? ????? 101 ????? 00100 11", Instruction::Immediate(ImmediateType::BEXTI)),
+  #[cfg(feature = "zbs")] InstPattern::new("011010? ????? ????? 001 ????? 00100 11", Instruction::Immediate(ImmediateType::BINVI)),
+  #[cfg(feature = "zbs")] InstPattern::new("001010? ????? ????? 001 ????? 00100 11", Instruction::Immediate(ImmediateType::BSETI)),
     // Transfer Control
   InstPattern::new("0000000 00001 00000 000 00000 11100 11", Instruction::Immediate(ImmediateType::EBREAK)),
   InstPattern::new("0000000 00000 00000 000 00000 11100 11", Instruction::Immediate(ImmediateType::ECALL)),
@@ -136,156 +862,1141 @@ impl Cpu {
   // InstPattern::new("??????? ????? ????? 000 ????? 00110 11", Instruction::ADDIW),
   // InstPattern::new("??????? ????? ????? 010 ????? 11100 11", Instruction::CSRRS),
   // InstPattern::new("??????? ????? ????? 001 ????? 11100 11", Instruction::CSRRW),
-  // InstPattern::new("000000? ????? ????? 001 ????? 00110 11", Instruction::SLLIW),
-  // InstPattern::new("010000? ????? ????? 101 ????? 00110 11", Instruction::SRAIW),
-  // InstPattern::new("000000? ????? ????? 101 ????? 00110 11", Instruction::SRLIW),
   // InstPattern::new("0000000 ????? ????? 000 ????? 01110 11", Instruction::ADDW),
-  // InstPattern::new("0000001 ????? ????? 100 ????? 01110 11", Instruction::DIVW),
-  // InstPattern::new("0000001 ????? ????? 101 ????? 01110 11", Instruction::DIVUW),
-  // InstPattern::new("0000001 ????? ????? 110 ????? 01110 11", Instruction::REMW),
-  // InstPattern::new("0000001 ????? ????? 111 ????? 01110 11", Instruction::REMUW),
-  // InstPattern::new("0000000 ????? ????? 001 ????? 01110 11", Instruction::SLLW),
-  // InstPattern::new("0100000 ????? ????? 101 ????? 01110 11", Instruction::SRAW),
-  // InstPattern::new("0000000 ????? ????? 101 ????? 01110 11", Instruction::SRLW),
   // InstPattern::new("0100000 ????? ????? 000 ????? 01110 11", Instruction::SUBW),
+];
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::memory::paddr::with_pmem_slice;
+  use memory::{read_data, write_data};
+
+  // place a program in pmem and point the cpu at it
+  fn load_program(cpu: &mut Cpu, base: u64, program: &[u32]) {
+    for (i, inst) in program.iter().enumerate() {
+      write_data(base + i as u64 * 4, 4, *inst as u64);
+    }
+    cpu.pc = base;
+    cpu.snpc = base;
+    cpu.dnpc = base;
+  }
+
+  // decode and execute a single instruction without going through pmem
+  fn exec_inst(cpu: &mut Cpu, inst: u32) {
+    let mut inst_type = Instruction::Immediate(ImmediateType::EBREAK);
+    cpu.inst = inst;
+    cpu.snpc = cpu.pc.wrapping_add(4);
+    assert!(cpu.decode(&mut inst_type), "0x{:08x} was not decoded", inst);
+    cpu.execute(inst_type);
+    cpu.pc = cpu.dnpc;
+  }
+
+  #[test]
+  fn test_history_on_bad_trap() {
+    let cpu = &mut Cpu::new();
+    cpu.set_history_size(2);
+    load_program(
+      cpu,
+      0x80100000,
+      &[
+        0x00100513, // addi a0, zero, 1
+        0x00200593, // addi a1, zero, 2
+        0x00100073, // ebreak
+      ],
+    );
+
+    assert_eq!(cpu.exec(usize::MAX), -1);
+
+    let history: Vec<_> =
+      cpu.recent_history().map(|e| (e.pc, e.inst)).collect();
+    assert_eq!(
+      history,
+      vec![(0x80100004, 0x00200593), (0x80100008, 0x00100073)]
+    );
+  }
+
+  #[test]
+  fn test_history_disabled_by_default() {
+    let cpu = &mut Cpu::new();
+    load_program(cpu, 0x80100100, &[0x00000513, 0x00100073]);
+
+    assert_eq!(cpu.exec(usize::MAX), 0);
+    assert_eq!(cpu.recent_history().count(), 0);
+  }
+
+  #[test]
+  fn test_upper_negative_imm() {
+    let cpu = &mut Cpu::new();
+    cpu.pc = 0x80002000;
+
+    exec_inst(cpu, 0x800000b7); // lui ra, 0x80000
+    assert_eq!(cpu.gpr[1], 0xffff_ffff_8000_0000);
+    assert_eq!(disassemble(0x80002000, 0x800000b7), "lui\tra,0x80000");
+
+    exec_inst(cpu, 0xfffff117); // auipc sp, 0xfffff
+    assert_eq!(cpu.gpr[2], 0x80002004 - 0x1000);
+    assert_eq!(disassemble(0x80002004, 0xfffff117), "auipc\tsp,0xfffff");
+  }
+
+  // RV64I arithmetic and effective addresses wrap, none of them may trap
+  #[test]
+  fn test_arith_wraps() {
+    let cpu = &mut Cpu::new();
+    cpu.gpr[1] = i64::MIN as u64;
+    cpu.gpr[2] = 1;
+    exec_inst(cpu, 0x402081b3); // sub gp, ra, sp
+    assert_eq!(cpu.gpr[3], i64::MAX as u64);
+
+    cpu.gpr[1] = i64::MAX as u64;
+    exec_inst(cpu, 0x002081b3); // add gp, ra, sp
+    assert_eq!(cpu.gpr[3], i64::MIN as u64);
+    exec_inst(cpu, 0x00108193); // addi gp, ra, 1
+    assert_eq!(cpu.gpr[3], i64::MIN as u64);
+
+    // a negative offset is a wrapping add of its two's complement
+    write_data(0x80101c00, 8, 0x1122334455667788);
+    cpu.gpr[5] = 0x80101c00 + 0x800;
+    exec_inst(cpu, 0x8002b303); // ld t1, -2048(t0)
+    assert_eq!(cpu.gpr[6], 0x1122334455667788);
+  }
+
+  #[test]
+  fn test_out_of_bound_aborts() {
+    let cpu = &mut Cpu::new();
+    cpu.set_history_size(4);
+    load_program(
+      cpu,
+      0x80101f00,
+      &[
+        0x00100293, // addi t0, zero, 1
+        0x0002b303, // ld t1, 0(t0)
+      ],
+    );
+    cpu.gpr[6] = 0x1234;
+    assert_eq!(cpu.exec(usize::MAX), -1);
+    assert_eq!(cpu.exit_reason(), ExitReason::Aborted);
+    assert_eq!(cpu.pc, 0x80101f04);
+    // the aborted load leaves its destination alone
+    assert_eq!(cpu.gpr[6], 0x1234);
+    assert_eq!(
+      cpu
+        .recent_history()
+        .map(|entry| entry.pc)
+        .collect::<Vec<_>>(),
+      [0x80101f00, 0x80101f04]
+    );
+
+    // a store is dropped, a jump off memory aborts on the fetch
+    let cpu = &mut Cpu::new();
+    load_program(
+      cpu,
+      0x80101f10,
+      &[
+        0x0062b023, // sd t1, 0(t0)
+      ],
+    );
+    cpu.exec(usize::MAX);
+    assert_eq!(cpu.exit_reason(), ExitReason::Aborted);
+    let cpu = &mut Cpu::new();
+    load_program(
+      cpu,
+      0x80101f20,
+      &[
+        0x00000067, // jalr zero, 0(zero)
+      ],
+    );
+    cpu.set_history_size(4);
+    cpu.exec(usize::MAX);
+    assert_eq!(cpu.exit_reason(), ExitReason::Aborted);
+    assert_eq!(cpu.pc, 0);
+    let last = cpu.recent_history().last().unwrap();
+    assert!(last.fetch_fault);
+    assert_eq!(last.to_string(), "0x0000000000000000: fetch fault");
+  }
+
+  // pc relative results wrap around the address space instead of overflowing
+  #[test]
+  fn test_pc_relative_wraps() {
+    let cpu = &mut Cpu::new();
+    cpu.pc = 0x7fff_ffff_ffff_f800;
+    exec_inst(cpu, 0x00001517); // auipc a0, 0x1
+    assert_eq!(cpu.gpr[10], 0x8000_0000_0000_0800);
+
+    cpu.pc = 0x800;
+    exec_inst(cpu, 0xfffff517); // auipc a0, 0xfffff
+    assert_eq!(cpu.gpr[10], 0xffff_ffff_ffff_f800);
+
+    cpu.pc = 0x8;
+    exec_inst(cpu, 0xff1ff0ef); // jal ra, -16
+    assert_eq!(cpu.pc, 0xffff_ffff_ffff_fff8);
+    assert_eq!(cpu.gpr[1], 0xc);
+
+    cpu.gpr[5] = u64::MAX;
+    exec_inst(cpu, 0x00428067); // jalr zero, 4(t0)
+    assert_eq!(cpu.pc, 0x2);
+  }
+
+  #[test]
+  fn test_upper_imm_matches_decode() {
+    for inst in [0x800000b7u32, 0xfffff117, 0x123450b7, 0x00001117] {
+      let expected = (inst & 0xfffff000) as i32 as i64;
+      let (_, _, _, imm) =
+        decode_operand(inst, Instruction::Upper(UpperType::LUI));
+      assert_eq!(imm, expected);
+    }
+  }
+
+  #[test]
+  #[cfg(feature = "zbs")]
+  fn test_zbs() {
+    let cpu = &mut Cpu::new();
+    cpu.gpr[1] = 0x0f;
+    cpu.gpr[2] = 63;
+
+    exec_inst(cpu, 0x282091b3); // bset gp, ra, sp
+    assert_eq!(cpu.gpr[3], 0x8000_0000_0000_000f);
+
+    exec_inst(cpu, 0x4820d213); // bexti tp, ra, 2
+    assert_eq!(cpu.gpr[4], 1);
+    exec_inst(cpu, 0x4840d213); // bexti tp, ra, 4
+    assert_eq!(cpu.gpr[4], 0);
+
+    exec_inst(cpu, 0x4821d193); // bext gp, gp, sp
+    assert_eq!(cpu.gpr[3], 1);
+
+    exec_inst(cpu, 0x48009193); // bclri gp, ra, 0
+    assert_eq!(cpu.gpr[3], 0x0e);
+
+    exec_inst(cpu, 0x6bf09193); // binvi gp, ra, 63
+    assert_eq!(cpu.gpr[3], 0x8000_0000_0000_000f);
+  }
+
+  #[test]
+  #[cfg(feature = "m")]
+  fn test_mulh_family() {
+    let cpu = &mut Cpu::new();
+    let cases: [(u64, u64); 4] = [
+      ((-3i64) as u64, u64::MAX),
+      ((-1i64) as u64, 0x8000_0000_0000_0000),
+      (i64::MIN as u64, u64::MAX),
+      (0x1234_5678_9abc_def0, 0xfedc_ba98_7654_3210),
     ];
-    for pattern in patterns.iter() {
-      if match_inst(self.inst, pattern.pattern) {
-        *inst_type = pattern.itype;
-        return;
-      }
+    for (a, b) in cases {
+      cpu.gpr[1] = a;
+      cpu.gpr[2] = b;
+
+      exec_inst(cpu, 0x022091b3); // mulh gp, ra, sp
+      assert_eq!(
+        cpu.gpr[3],
+        ((a as i64 as i128 * b as i64 as i128) >> 64) as u64
+      );
+
+      exec_inst(cpu, 0x0220a1b3); // mulhsu gp, ra, sp
+      assert_eq!(cpu.gpr[3], ((a as i64 as i128 * b as i128) >> 64) as u64);
+
+      exec_inst(cpu, 0x0220b1b3); // mulhu gp, ra, sp
+      assert_eq!(cpu.gpr[3], ((a as u128 * b as u128) >> 64) as u64);
+
+      exec_inst(cpu, 0x022081b3); // mul gp, ra, sp
+      assert_eq!(cpu.gpr[3], a.wrapping_mul(b));
     }
   }
 
-  #[rustfmt::skip]
-  pub fn execute(&mut self, inst_type: Instruction) {
-    let (rd, rs1, rs2, imm) = decode_operand(self.inst, inst_type);
-    self.dnpc = self.snpc;
-    match inst_type {
-      Instruction::Register(RegisterType::ADD)  => {self.gpr[rd] = (self.gpr[rs1] as i64 + self.gpr[rs2] as i64) as u64;}
-      Instruction::Register(RegisterType::SUB)  => {self.gpr[rd] = (self.gpr[rs1] as i64 - self.gpr[rs2] as i64) as u64;}
-      Instruction::Register(RegisterType::XOR)  => {self.gpr[rd] = self.gpr[rs1] ^ self.gpr[rs2];}
-      Instruction::Register(RegisterType::OR)   => {self.gpr[rd] = self.gpr[rs1] | self.gpr[rs2];}
-      Instruction::Register(RegisterType::AND)  => {self.gpr[rd] = self.gpr[rs1] & self.gpr[rs2];}
-      Instruction::Register(RegisterType::SLL)  => {self.gpr[rd] = self.gpr[rs1] << self.gpr[rs2];}
-      Instruction::Register(RegisterType::SRL)  => {self.gpr[rd] = self.gpr[rs1] >> self.gpr[rs2];}
-      Instruction::Register(RegisterType::SLT)  => {self.gpr[rd] = if (self.gpr[rs1] as i64) < (self.gpr[rs2] as i64) {1} else {0};}
-      Instruction::Register(RegisterType::SLTU) => {self.gpr[rd] = if self.gpr[rs1] < self.gpr[rs2] {1} else {0};}
+  #[test]
+  #[cfg(feature = "m")]
+  fn test_mulhsu_negative_rs1() {
+    let cpu = &mut Cpu::new();
+    // -1 * (2^64 - 1) = -(2^64 - 1), whose high doubleword is all ones
+    cpu.gpr[1] = (-1i64) as u64;
+    cpu.gpr[2] = u64::MAX;
+    exec_inst(cpu, 0x0220a1b3); // mulhsu gp, ra, sp
+    assert_eq!(cpu.gpr[3], u64::MAX);
 
-      Instruction::Immediate(ImmediateType::ADDI)  => {self.gpr[rd] = (self.gpr[rs1] as i64 + imm) as u64;}
-      Instruction::Immediate(ImmediateType::XORI)  => {self.gpr[rd] = self.gpr[rs1] ^ imm as u64;}
-      Instruction::Immediate(ImmediateType::ORI)   => {self.gpr[rd] = self.gpr[rs1] | imm as u64;}
-      Instruction::Immediate(ImmediateType::ANDI)  => {self.gpr[rd] = self.gpr[rs1] & imm as u64;}
-      Instruction::Immediate(ImmediateType::SLLI)  => {self.gpr[rd] = self.gpr[rs1] << imm;}
-      Instruction::Immediate(ImmediateType::SRLI)  => {self.gpr[rd] = self.gpr[rs1] >> imm;}
-      Instruction::Immediate(ImmediateType::SRAI)  => {self.gpr[rd] = (self.gpr[rs1] as i64 >> imm) as u64;}
-      Instruction::Immediate(ImmediateType::SLTI)  => {self.gpr[rd] = if (self.gpr[rs1] as i64) < imm {1} else {0};}
-      Instruction::Immediate(ImmediateType::SLTIU) => {self.gpr[rd] = if self.gpr[rs1] < imm as u64 {1} else {0};}
+    // -2 * 2^63 = -2^64, high doubleword is -1
+    cpu.gpr[1] = (-2i64) as u64;
+    cpu.gpr[2] = 0x8000_0000_0000_0000;
+    exec_inst(cpu, 0x0220a1b3); // mulhsu gp, ra, sp
+    assert_eq!(cpu.gpr[3], u64::MAX);
+  }
 
-      Instruction::Immediate(ImmediateType::LB)  => {sext(read_data((self.gpr[rs1] as i64 + imm) as u64, 1) as usize, 8);}
-      Instruction::Immediate(ImmediateType::LBU) => {read_data((self.gpr[rs1] as i64 + imm) as u64, 1);}
-      Instruction::Immediate(ImmediateType::LH)  => {sext(read_data((self.gpr[rs1] as i64 + imm) as u64, 2) as usize, 8);}
-      Instruction::Immediate(ImmediateType::LHU) => {read_data((self.gpr[rs1] as i64 + imm) as u64, 2);}
-      Instruction::Immediate(ImmediateType::LW)  => {sext(read_data((self.gpr[rs1] as i64 + imm) as u64, 4) as usize, 8);}
-      Instruction::Immediate(ImmediateType::LWU) => {read_data((self.gpr[rs1] as i64 + imm) as u64, 4);}
-      Instruction::Immediate(ImmediateType::LD)  => {sext(read_data((self.gpr[rs1] as i64 + imm) as u64, 8) as usize, 8);}
-      Instruction::Immediate(ImmediateType::LDU) => {read_data((self.gpr[rs1] as i64 + imm) as u64, 8);}
-
-      Instruction::Store(StoreType::SB) => {write_data((self.gpr[rs1] as i64 + imm) as u64, 1, self.gpr[rs2]);}
-      Instruction::Store(StoreType::SH) => {write_data((self.gpr[rs1] as i64 + imm) as u64, 2, self.gpr[rs2]);}
-      Instruction::Store(StoreType::SW) => {write_data((self.gpr[rs1] as i64 + imm) as u64, 4, self.gpr[rs2]);}
-      Instruction::Store(StoreType::SD) => {write_data((self.gpr[rs1] as i64 + imm) as u64, 8, self.gpr[rs2]);}
-
-      Instruction::Branch(BranchType::BEQ)  => {if self.gpr[rs1] == self.gpr[rs2] {self.pc = (self.pc as i64 + imm) as u64;}}
-      Instruction::Branch(BranchType::BNE)  => {if self.gpr[rs1] != self.gpr[rs2] {self.pc = (self.pc as i64 + imm) as u64;}}
-      Instruction::Branch(BranchType::BLT)  => {if (self.gpr[rs1] as i64) < (self.gpr[rs2] as i64) {self.pc = (self.pc as i64 + imm) as u64;}}
-      Instruction::Branch(BranchType::BGE)  => {if (self.gpr[rs1] as i64) >= (self.gpr[rs2] as i64) {self.pc = (self.pc as i64 + imm) as u64;}}
-      Instruction::Branch(BranchType::BLTU) => {if self.gpr[rs1] < self.gpr[rs2] {self.pc = (self.pc as i64 + imm) as u64;}}
-      Instruction::Branch(BranchType::BGEU) => {if self.gpr[rs1] >= self.gpr[rs2] {self.pc = (self.pc as i64 + imm) as u64;}}
-
-      Instruction::Jump(JumpType::JAL)            => {self.gpr[rd] = self.pc + 4; self.dnpc = (self.pc as i64 + imm) as u64;}
-      Instruction::Immediate(ImmediateType::JALR) => {self.gpr[rd] = self.pc + 4; self.dnpc = (self.gpr[rs1] as i64 + imm) as u64;}
-
-      Instruction::Upper(UpperType::LUI)   => {self.gpr[rd] = self.gpr[rs1];}
-      Instruction::Upper(UpperType::AUIPC) => {self.gpr[rd] = (self.pc as i64 + imm) as u64;}
-
-      Instruction::Immediate(ImmediateType::ECALL)  => {todo!();}
-      Instruction::Immediate(ImmediateType::EBREAK) => {self.hemu_trap();}
+  #[test]
+  #[cfg(feature = "m")]
+  fn test_mulw_sign_extends() {
+    let cpu = &mut Cpu::new();
+    cpu.gpr[1] = 0x1_0000_8000;
+    cpu.gpr[2] = 0x1_0001_0000;
+    exec_inst(cpu, 0x022081bb); // mulw gp, ra, sp
+    assert_eq!(cpu.gpr[3], 0xffff_ffff_8000_0000);
+  }
 
-      _ => {todo!("{:?} not implemented", inst_type);}
+  #[test]
+  fn test_count_only_matches_trace() {
+    let mut program = vec![];
+    for i in 1..32u32 {
+      program.push(i << 20 | i << 7 | 0x13); // addi xi, zero, i
+      program.push(0x00a00033 | i << 7 | i << 15); // add xi, xi, a0
     }
-    self.gpr[0] = 0;
+    program.push(0x00100073); // ebreak
+
+    let traced = &mut Cpu::new();
+    load_program(traced, 0x80100300, &program);
+    traced.exec(usize::MAX);
+
+    let fast = &mut Cpu::new();
+    fast.set_count_only(true);
+    fast.set_history_size(4);
+    load_program(fast, 0x80100300, &program);
+    fast.exec(usize::MAX);
+
+    assert_eq!(fast.gpr, traced.gpr);
+    assert_eq!(fast.pc, traced.pc);
+    assert_eq!(fast.statistic.count, traced.statistic.count);
+    assert_eq!(fast.recent_history().count(), 0);
   }
 
-  fn exec_once(&mut self) {
-    // pipeline start
-    let mut inst_type = Instruction::Immediate(ImmediateType::EBREAK);
-    // fetch stage
-    self.fetch();
-    // decode stage
-    self.decode(&mut inst_type);
-    // execute stage (including memory stage and write back stage)
-    self.execute(inst_type);
-    // update pc
-    self.pc = self.dnpc;
+  #[test]
+  fn test_mem_record() {
+    let cpu = &mut Cpu::new();
+    cpu.set_record_depth(3);
+    load_program(
+      cpu,
+      0x80100400,
+      &[
+        0x00000097, // auipc ra, 0
+        0xffff0137, // lui sp, 0xffff0
+        0x1020b023, // sd sp, 256(ra)
+        0x1000c183, // lbu gp, 256(ra)
+        0x10209223, // sh sp, 260(ra)
+        0x1040a203, // lw tp, 260(ra)
+      ],
+    );
+
+    cpu.exec(6);
+    let accesses: Vec<_> = cpu.mem_accesses().copied().collect();
+    assert_eq!(
+      accesses,
+      vec![
+        MemAccess {
+          kind: AccessKind::Read,
+          addr: 0x80100500,
+          len: 1,
+          data: 0x00
+        },
+        MemAccess {
+          kind: AccessKind::Write,
+          addr: 0x80100504,
+          len: 2,
+          data: 0x0000
+        },
+        MemAccess {
+          kind: AccessKind::Read,
+          addr: 0x80100504,
+          len: 4,
+          data: 0xffff_0000
+        },
+      ]
+    );
+    assert_eq!(cpu.gpr[4], 0xffff_ffff_ffff_0000);
+
+    // the record only covers the latest exec call
+    cpu.exec(0);
+    assert_eq!(cpu.mem_accesses().count(), 0);
   }
 
-  fn exec_ntimes(&mut self, n: usize) {
-    for _ in 0..n {
-      self.exec_once();
-      self.statistic.inc_count();
-      if self.state != CpuState::Running {
-        break;
-      }
+  // every register-register op added on top of RV64I must leave x0, the
+  // other registers and memory untouched when rd is x0
+  #[test]
+  fn test_new_ops_rd_zero() {
+    #[rustfmt::skip]
+    let ops: &[(&str, u32)] = &[
+      #[cfg(feature = "zbs")] ("bclr",   0x4820_9033),
+      #[cfg(feature = "zbs")] ("bext",   0x4820_d033),
+      #[cfg(feature = "zbs")] ("binv",   0x6820_9033),
+      #[cfg(feature = "zbs")] ("bset",   0x2820_9033),
+      #[cfg(feature = "m")]   ("mul",    0x0220_8033),
+      #[cfg(feature = "m")]   ("mulh",   0x0220_9033),
+      #[cfg(feature = "m")]   ("mulhsu", 0x0220_a033),
+      #[cfg(feature = "m")]   ("mulhu",  0x0220_b033),
+      #[cfg(feature = "m")]   ("mulw",   0x0220_803b),
+      #[cfg(feature = "m")]   ("div",    0x0220_c033),
+      #[cfg(feature = "m")]   ("divu",   0x0220_d033),
+      #[cfg(feature = "m")]   ("rem",    0x0220_e033),
+      #[cfg(feature = "m")]   ("remu",   0x0220_f033),
+      #[cfg(feature = "m")]   ("divw",   0x0220_c03b),
+      #[cfg(feature = "m")]   ("divuw",  0x0220_d03b),
+      #[cfg(feature = "m")]   ("remw",   0x0220_e03b),
+      #[cfg(feature = "m")]   ("remuw",  0x0220_f03b),
+    ];
+    for &(name, inst) in ops {
+      let cpu = &mut Cpu::new();
+      cpu.set_record_depth(4);
+      cpu.gpr[1] = 0xdead_beef_dead_beef;
+      cpu.gpr[2] = 0x8000_0000_0000_0005;
+      let gpr = cpu.gpr;
+
+      exec_inst(cpu, inst); // <op> zero, ra, sp
+      assert!(cpu.state == CpuState::Running, "{} was not decoded", name);
+      assert_eq!(cpu.gpr, gpr, "{} changed the register file", name);
+      assert_eq!(cpu.mem_accesses().count(), 0, "{} touched memory", name);
+      assert_eq!(cpu.pc, 0x80000004, "{} changed control flow", name);
     }
   }
 
-  fn statistic(&self) {
-    log::info!("host time spend = {:?}", self.statistic.time);
-    log::info!("total guest instructions = {:?}", self.statistic.count);
-    log::info!(
-      "simulation frequency = {:?}",
-      (self.statistic.count) as f64 / (self.statistic.time.as_secs_f64())
+  #[test]
+  fn test_shift_amounts() {
+    let cpu = &mut Cpu::new();
+    cpu.gpr[1] = 0x8000_0000_0000_0001;
+
+    exec_inst(cpu, 0x00009193); // slli gp, ra, 0
+    assert_eq!(cpu.gpr[3], 0x8000_0000_0000_0001);
+    exec_inst(cpu, 0x03f09193); // slli gp, ra, 63
+    assert_eq!(cpu.gpr[3], 0x8000_0000_0000_0000);
+    exec_inst(cpu, 0x03f0d193); // srli gp, ra, 63
+    assert_eq!(cpu.gpr[3], 1);
+    exec_inst(cpu, 0x43f0d193); // srai gp, ra, 63
+    assert_eq!(cpu.gpr[3], u64::MAX);
+
+    // only the low six bits of rs2 count
+    cpu.gpr[2] = 64 + 63;
+    exec_inst(cpu, 0x002091b3); // sll gp, ra, sp
+    assert_eq!(cpu.gpr[3], 0x8000_0000_0000_0000);
+    exec_inst(cpu, 0x0020d1b3); // srl gp, ra, sp
+    assert_eq!(cpu.gpr[3], 1);
+    exec_inst(cpu, 0x4020d1b3); // sra gp, ra, sp
+    assert_eq!(cpu.gpr[3], u64::MAX);
+  }
+
+  #[test]
+  fn test_word_shift_amounts() {
+    let cpu = &mut Cpu::new();
+    cpu.gpr[1] = 0xffff_ffff_8000_0001;
+
+    exec_inst(cpu, 0x01f0919b); // slliw gp, ra, 31
+    assert_eq!(cpu.gpr[3], 0xffff_ffff_8000_0000);
+    exec_inst(cpu, 0x01f0d19b); // srliw gp, ra, 31
+    assert_eq!(cpu.gpr[3], 1);
+    exec_inst(cpu, 0x41f0d19b); // sraiw gp, ra, 31
+    assert_eq!(cpu.gpr[3], u64::MAX);
+    exec_inst(cpu, 0x0000d19b); // srliw gp, ra, 0
+    assert_eq!(cpu.gpr[3], 0xffff_ffff_8000_0001);
+
+    // the word forms only use the low five bits of rs2
+    cpu.gpr[2] = 32 + 31;
+    exec_inst(cpu, 0x002091bb); // sllw gp, ra, sp
+    assert_eq!(cpu.gpr[3], 0xffff_ffff_8000_0000);
+    exec_inst(cpu, 0x0020d1bb); // srlw gp, ra, sp
+    assert_eq!(cpu.gpr[3], 1);
+    exec_inst(cpu, 0x4020d1bb); // sraw gp, ra, sp
+    assert_eq!(cpu.gpr[3], u64::MAX);
+
+    cpu.gpr[2] = 32;
+    exec_inst(cpu, 0x0020d1bb); // srlw gp, ra, sp
+    assert_eq!(cpu.gpr[3], 0xffff_ffff_8000_0001);
+  }
+
+  #[test]
+  fn test_step_until() {
+    let cpu = &mut Cpu::new();
+    load_program(
+      cpu,
+      0x80100600,
+      &[
+        0x00750513, // addi a0, a0, 7
+        0x00750513, // addi a0, a0, 7
+        0x00750513, // addi a0, a0, 7
+        0x00100073, // ebreak
+      ],
     );
+
+    assert_eq!(cpu.step_until(|cpu| cpu.gpr[10] == 14, 10).unwrap(), 2);
+    assert_eq!(cpu.pc, 0x80100608);
+    assert_eq!(cpu.step_until(|cpu| cpu.gpr[10] == 14, 10).unwrap(), 0);
+    assert!(cpu.step_until(|cpu| cpu.gpr[10] == 42, 1).is_err());
+    // ebreak ends the run before a0 ever reaches 42
+    assert!(cpu.step_until(|cpu| cpu.gpr[10] == 42, 10).is_err());
+    assert!(cpu.state == CpuState::Ended);
   }
 
-  pub fn exec(&mut self, n: usize) -> i32 {
-    let start_time = self.statistic.start_timer();
+  #[test]
+  fn test_boot_config_reset_pc() {
+    let boot = BootConfig {
+      reset_pc: 0x80100700,
+      stack_top: 0x80200000,
+    };
+    write_data(0x80100700, 4, 0x00500513); // addi a0, zero, 5
+    write_data(0x80100704, 4, 0x00100073); // ebreak
 
-    self.exec_ntimes(n);
+    let cpu = &mut Cpu::with_boot_config(boot);
+    assert_eq!(cpu.gpr[2], 0x80200000);
+    cpu.set_history_size(1);
+    cpu.exec(1);
+    assert_eq!(cpu.recent_history().next().unwrap().pc, 0x80100700);
+    assert_eq!(cpu.gpr[10], 5);
 
-    self.statistic.stop_timer(start_time);
+    cpu.exec(usize::MAX);
+    assert!(cpu.state == CpuState::Ended);
 
-    match self.state {
-      CpuState::Ended => {
-        if self.halt.ret == 0 {
-          println!("{}", Green.bold().paint("HIT GOOD TRAP"));
-          self.statistic();
-          return 0;
-        } else {
-          log::error!("{}", Red.bold().paint("HIT BAD TRAP"));
-          return -1;
-        }
-      }
-      CpuState::Running => {}
-      CpuState::Quit => {
-        self.statistic();
-      }
+    cpu.gpr[2] = 0;
+    cpu.reset();
+    assert_eq!(cpu.pc, 0x80100700);
+    assert_eq!((cpu.gpr[2], cpu.gpr[10]), (0x80200000, 0));
+    assert!(cpu.state == CpuState::Running);
+  }
+
+  #[test]
+  fn test_unimp_aborts() {
+    for (base, unimp) in [(0x80100800, 0x00000000), (0x80100810, 0xc0001073)] {
+      let cpu = &mut Cpu::new();
+      cpu.set_history_size(2);
+      load_program(
+        cpu,
+        base,
+        &[
+          0x00000513, // addi a0, zero, 0
+          unimp,      // all-zero or csrrw x0, cycle, x0
+          0x00100073, // ebreak
+        ],
+      );
+
+      assert_eq!(cpu.exec(usize::MAX), -1);
+      assert!(cpu.state == CpuState::Aborted);
+      assert_eq!(cpu.pc, base + 4);
+      let last = cpu.recent_history().last().unwrap();
+      assert_eq!((last.pc, last.inst), (base + 4, unimp));
+      assert_eq!(
+        last.to_string(),
+        format!("0x{:016x}: 0x{:08x} unimp", base + 4, unimp)
+      );
     }
-    0
   }
 
-  pub fn dump_registers(&self) {
-    for i in 0..32 {
-      print!("x{:02} = 0x{:016x} ", i, self.gpr[i]);
-      if i % 4 == 3 {
-        println!();
-      }
+  #[test]
+  fn test_ecall_aborts() {
+    let cpu = &mut Cpu::new();
+    cpu.set_history_size(2);
+    load_program(
+      cpu,
+      0x80100840,
+      &[
+        0x00000073, // ecall
+        0x00100073, // ebreak
+      ],
+    );
+    assert_eq!(cpu.exec(usize::MAX), -1);
+    assert_eq!(cpu.exit_reason(), ExitReason::Aborted);
+    assert_eq!(cpu.pc, 0x80100840);
+    let last = cpu.recent_history().last().unwrap();
+    assert_eq!(last.to_string(), "0x0000000080100840: 0x00000073 ecall");
+  }
+
+  #[test]
+  #[cfg(not(all(feature = "m", feature = "zbs")))]
+  fn test_disabled_extension_aborts() {
+    #[cfg(not(feature = "m"))]
+    let inst = 0x022081b3; // mul gp, ra, sp
+    #[cfg(all(feature = "m", not(feature = "zbs")))]
+    let inst = 0x282091b3; // bset gp, ra, sp
+    let cpu = &mut Cpu::new();
+    load_program(cpu, 0x80100a00, &[inst, 0x00100073]);
+
+    assert_eq!(cpu.exec(usize::MAX), -1);
+    assert!(cpu.state == CpuState::Aborted);
+    assert_eq!(cpu.pc, 0x80100a00);
+  }
+
+  #[test]
+  fn test_step_retire_record() {
+    let cpu = &mut Cpu::new();
+    load_program(
+      cpu,
+      0x80100b00,
+      &[
+        0x00000097, // auipc ra, 0
+        0x00500513, // addi a0, zero, 5
+        0x00a0a823, // sw a0, 16(ra)
+        0x00100073, // ebreak
+      ],
+    );
+
+    cpu.step();
+    let addi = cpu.step();
+    assert_eq!(
+      (addi.pc, addi.next_pc, addi.inst),
+      (0x80100b04, 0x80100b08, 0x00500513)
+    );
+    assert_eq!(addi.rd, Some((10, 5)));
+    assert_eq!(addi.mem, None);
+
+    let sw = cpu.step();
+    assert_eq!((sw.pc, sw.next_pc), (0x80100b08, 0x80100b0c));
+    assert_eq!(sw.rd, None);
+    assert_eq!(
+      sw.mem,
+      Some(MemAccess {
+        kind: AccessKind::Write,
+        addr: 0x80100b10,
+        len: 4,
+        data: 5
+      })
+    );
+
+    let ebreak = cpu.step();
+    assert_eq!((ebreak.rd, ebreak.mem), (None, None));
+    assert!(cpu.state == CpuState::Ended);
+  }
+
+  #[test]
+  fn test_progress_limit() {
+    let cpu = &mut Cpu::new();
+    cpu.set_progress_limit(100);
+    load_program(
+      cpu,
+      0x80100c00,
+      &[
+        0x0000006f, // jal zero, 0
+      ],
+    );
+    assert_eq!(cpu.exec(1000), -1);
+    assert!(cpu.state == CpuState::Aborted);
+    assert_eq!(cpu.pc, 0x80100c00);
+
+    // a pc that keeps changing a register is making progress, repeating
+    // the same value is not
+    let mut progress = ProgressMonitor::new(3);
+    let mut record = RetireRecord {
+      pc: 0x80100c10,
+      next_pc: 0x80100c10,
+      inst: 0x00150513, // addi a0, a0, 1
+      rd: None,
+      mem: None,
+    };
+    for value in 1..10 {
+      record.rd = Some((10, value));
+      assert!(!progress.stalled(&record));
     }
-    println!();
+    assert!(!progress.stalled(&record));
+    assert!(!progress.stalled(&record));
+    assert!(progress.stalled(&record));
   }
 
-  pub fn dump_watches(&self) {
-    todo!("watch points not implemented!");
+  #[test]
+  fn test_trace_hash() {
+    let run = |program: &[u32]| {
+      let cpu = &mut Cpu::new();
+      cpu.set_trace_hash(true);
+      load_program(cpu, 0x80100e00, program);
+      cpu.exec(usize::MAX);
+      cpu.trace_hash().unwrap()
+    };
+    let program = [
+      0x00000097, // auipc ra, 0
+      0x00500513, // addi a0, zero, 5
+      0x02a0b023, // sd a0, 32(ra)
+      0x00000513, // addi a0, zero, 0
+      0x00100073, // ebreak
+    ];
+    let mut altered = program;
+    altered[1] = 0x00600513; // addi a0, zero, 6
+
+    assert_eq!(run(&program), run(&program));
+    assert_ne!(run(&program), run(&altered));
+    assert!(Cpu::new().trace_hash().is_none());
+  }
+
+  #[test]
+  fn test_coalesced_stores() {
+    let cpu = &mut Cpu::new();
+    cpu.set_record_depth(8);
+    load_program(
+      cpu,
+      0x80100f00,
+      &[
+        0x00000097, // auipc ra, 0
+        0x01100513, // addi a0, zero, 0x11
+        0x04a08023, // sb a0, 64(ra)
+        0x02200513, // addi a0, zero, 0x22
+        0x04a080a3, // sb a0, 65(ra)
+        0x03300513, // addi a0, zero, 0x33
+        0x04a08123, // sb a0, 66(ra)
+        0x04400513, // addi a0, zero, 0x44
+        0x04a081a3, // sb a0, 67(ra)
+        0x0400b583, // ld a1, 64(ra)
+      ],
+    );
+    cpu.exec(10);
+
+    assert_eq!(cpu.mem_accesses().count(), 5);
+    assert_eq!(
+      cpu.record.coalesced(8),
+      vec![
+        MemAccess {
+          kind: AccessKind::Write,
+          addr: 0x80100f40,
+          len: 4,
+          data: 0x44332211
+        },
+        MemAccess {
+          kind: AccessKind::Read,
+          addr: 0x80100f40,
+          len: 8,
+          data: 0x44332211
+        },
+      ]
+    );
+    // a window smaller than the run splits it
+    assert_eq!(cpu.record.coalesced(2).len(), 3);
+  }
+
+  #[test]
+  fn test_control_flow_targets() {
+    let cpu = &mut Cpu::new();
+    cpu.gpr[1] = 1;
+    exec_inst(cpu, 0x00008463); // beq ra, zero, 8
+    assert_eq!(cpu.pc, 0x80000004);
+    exec_inst(cpu, 0x00009463); // bne ra, zero, 8
+    assert_eq!(cpu.pc, 0x8000000c);
+    exec_inst(cpu, 0xfe009ee3); // bne ra, zero, -4
+    assert_eq!(cpu.pc, 0x80000008);
+
+    exec_inst(cpu, 0x010000ef); // jal ra, 16
+    assert_eq!((cpu.pc, cpu.gpr[1]), (0x80000018, 0x8000000c));
+    // the target is computed from rs1 before rd is overwritten, bit 0 cleared
+    cpu.gpr[1] = 0x80000101;
+    exec_inst(cpu, 0x000080e7); // jalr ra, 0(ra)
+    assert_eq!((cpu.pc, cpu.gpr[1]), (0x80000100, 0x8000001c));
+  }
+
+  #[test]
+  fn test_backward_branch_loop() {
+    let cpu = &mut Cpu::new();
+    load_program(
+      cpu,
+      0x80101000,
+      &[
+        0x00a00593, // addi a1, zero, 10
+        0x00160613, // addi a2, a2, 1
+        0xfeb61ee3, // bne a2, a1, -4
+        0x00100073, // ebreak
+      ],
+    );
+    assert_eq!(cpu.exec(usize::MAX), 0);
+    assert!(cpu.state == CpuState::Ended);
+    assert_eq!(cpu.gpr[12], 10);
+    assert_eq!(cpu.pc, 0x80101010);
+  }
+
+  #[test]
+  fn test_icache_loop_hits() {
+    let cpu = &mut Cpu::new();
+    cpu.set_icache(Some(ICacheConfig {
+      sets: 4,
+      ways: 2,
+      line_size: 16,
+    }));
+    load_program(
+      cpu,
+      0x80101400,
+      &[
+        0x00a00593, // addi a1, zero, 10
+        0x00160613, // addi a2, a2, 1
+        0xfeb61ee3, // bne a2, a1, -4
+        0x00100073, // ebreak
+      ],
+    );
+    assert_eq!(cpu.exec(usize::MAX), 0);
+    // the whole loop sits in one line, only the first fetch misses
+    assert_eq!(cpu.icache_stats(), Some((21, 1)));
+
+    // two lines mapping to the same set evict each other in a direct-mapped cache
+    let mut icache = ICache::new(ICacheConfig {
+      sets: 2,
+      ways: 1,
+      line_size: 16,
+    });
+    assert!(!icache.access(0x80101400));
+    assert!(icache.access(0x8010140c));
+    assert!(!icache.access(0x80101420));
+    assert!(!icache.access(0x80101400));
+    assert_eq!((icache.hits, icache.misses), (1, 3));
+  }
+
+  #[test]
+  fn test_snapshot_round_trip() {
+    let cpu = &mut Cpu::new();
+    load_program(
+      cpu,
+      0x80101800,
+      &[
+        0x00000297, // auipc t0, 0
+        0x00a00593, // addi a1, zero, 10
+        0x0802b603, // ld a2, 128(t0)
+        0x00160613, // addi a2, a2, 1
+        0x08c2b023, // sd a2, 128(t0)
+        0xfeb61ae3, // bne a2, a1, -12
+        0x00100073, // ebreak
+      ],
+    );
+    write_data(0x80101880, 8, 0);
+    cpu.exec(14);
+    assert_eq!(read_data(0x80101880, 8), 3);
+
+    let mut snapshot = Snapshot::capture(cpu);
+    let mut file = Vec::new();
+    snapshot.write_to(&mut file).unwrap();
+    assert!(Snapshot::read_from(&mut &file[1..]).is_err());
+    cpu.exec(usize::MAX);
+    let finished = cpu.gpr;
+
+    // pmem is shared with the other tests, only bring back this program's page
+    snapshot = Snapshot::read_from(&mut file.as_slice()).unwrap();
+    snapshot.regions = vec![(0x80101800, 0x80101900)];
+    snapshot.restore(cpu).unwrap();
+    assert_eq!(read_data(0x80101880, 8), 3);
+    assert_eq!(cpu.gpr[12], 3);
+    assert!(cpu.state == CpuState::Running);
+
+    assert_eq!(cpu.exec(usize::MAX), 0);
+    assert_eq!(cpu.gpr, finished);
+    assert_eq!(cpu.pc, 0x8010181c);
+    assert_eq!(read_data(0x80101880, 8), 10);
+
+    snapshot.regions = vec![(0x70000000, 0x70001000)];
+    assert!(snapshot.restore(cpu).is_err());
+  }
+
+  #[test]
+  fn test_snapshot_file() {
+    let path = std::env::temp_dir()
+      .join(format!("hemu-snapshot-{}.bin", std::process::id()));
+    let cpu = &mut Cpu::new();
+    load_program(
+      cpu,
+      0x80102000,
+      &[
+        0x00000297, // auipc t0, 0
+        0x00a00593, // addi a1, zero, 10
+        0x0802b603, // ld a2, 128(t0)
+        0x00160613, // addi a2, a2, 1
+        0x08c2b023, // sd a2, 128(t0)
+        0xfeb61ae3, // bne a2, a1, -12
+        0x00100073, // ebreak
+      ],
+    );
+    write_data(0x80102080, 8, 0);
+    cpu.exec(14);
+    cpu.save_state(&path).unwrap();
+    let (saved_gpr, saved_pc) = (cpu.gpr, cpu.pc);
+    assert_eq!(cpu.exec(usize::MAX), 0);
+    assert_eq!(read_data(0x80102080, 8), 10);
+
+    // pmem is shared with the other tests, narrow the saved file down to
+    // this program's memory before loading it back
+    let mut snapshot =
+      Snapshot::read_from(&mut std::fs::File::open(&path).unwrap()).unwrap();
+    assert_eq!((snapshot.pc, snapshot.gpr), (saved_pc, saved_gpr));
+    snapshot.regions = vec![(0x80102000, 0x80102100)];
+    snapshot
+      .write_to(&mut std::fs::File::create(&path).unwrap())
+      .unwrap();
+
+    cpu.load_state(&path).unwrap();
+    assert_eq!(cpu.gpr, saved_gpr);
+    assert_eq!(cpu.pc, saved_pc);
+    assert_eq!(read_data(0x80102080, 8), 3);
+    assert_eq!(cpu.exec(usize::MAX), 0);
+    assert_eq!(read_data(0x80102080, 8), 10);
+
+    // the header is checked before anything is restored
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes[8] = 2;
+    std::fs::write(&path, &bytes).unwrap();
+    let err = cpu.load_state(&path).unwrap_err();
+    assert!(
+      err
+        .to_string()
+        .starts_with("unsupported snapshot version 2"),
+      "{}",
+      err
+    );
+    bytes[0] = b'X';
+    std::fs::write(&path, &bytes).unwrap();
+    let err = cpu.load_state(&path).unwrap_err();
+    assert_eq!(err.to_string(), "not a hemu snapshot");
+    assert_eq!(cpu.pc, 0x8010201c);
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn test_snapshot_bad_region() {
+    let path = std::env::temp_dir()
+      .join(format!("hemu-bad-snapshot-{}.bin", std::process::id()));
+    let cpu = &mut Cpu::new();
+    let mut snapshot = Snapshot {
+      pc: 0x80000000,
+      gpr: [0; 32],
+      regions: vec![(0x80102100, 0x80102000)],
+      pages: Vec::new(),
+    };
+    snapshot
+      .write_to(&mut std::fs::File::create(&path).unwrap())
+      .unwrap();
+    let err = cpu.load_state(&path).unwrap_err();
+    assert!(err.to_string().contains("ends before it starts"), "{}", err);
+
+    snapshot.regions = vec![(0x80102000, 0x80102100)];
+    snapshot.pages = vec![(u64::MAX - 4, vec![1; 8])];
+    snapshot
+      .write_to(&mut std::fs::File::create(&path).unwrap())
+      .unwrap();
+    let err = cpu.load_state(&path).unwrap_err();
+    assert!(
+      err.to_string().contains("wraps the address space"),
+      "{}",
+      err
+    );
+
+    snapshot.pages = vec![(0x80102000, vec![1; 0x1001])];
+    snapshot
+      .write_to(&mut std::fs::File::create(&path).unwrap())
+      .unwrap();
+    let err = cpu.load_state(&path).unwrap_err();
+    assert!(err.to_string().contains("larger than"), "{}", err);
+    assert_eq!(cpu.pc, 0x80000000);
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn test_trace_regs() {
+    let path = std::env::temp_dir()
+      .join(format!("hemu-trace-regs-{}.log", std::process::id()));
+    let cpu = &mut Cpu::new();
+    cpu.set_retire_trace(Box::new(std::fs::File::create(&path).unwrap()));
+    cpu.set_trace_regs(true);
+    load_program(
+      cpu,
+      0x80101a00,
+      &[
+        0x00500513, // addi a0, zero, 5
+        0x00a50593, // addi a1, a0, 10
+      ],
+    );
+    cpu.exec(2);
+    let trace = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let lines: Vec<&str> = trace.lines().collect();
+    assert_eq!(lines.len(), 2 * 9);
+    for step in lines.chunks(9) {
+      assert!(step[0].starts_with("0x0000000080101a0"), "{}", step[0]);
+      assert_eq!(
+        step[1..]
+          .iter()
+          .map(|line| line.matches(" = 0x").count())
+          .sum::<usize>(),
+        32
+      );
+    }
+    assert!(lines[3].starts_with("x08 = 0x0000000000000000 x09 = 0x0000000000000000 x10 = 0x0000000000000005 x11 = 0x0000000000000000"));
+    assert!(lines[12].contains("x11 = 0x000000000000000f"));
+  }
+
+  // the fast path must agree with the pattern table on every encoding
+  #[test]
+  fn test_decode_base_matches_table() {
+    const HOT: [u32; 10] = [
+      0b0110011, 0b0010011, 0b0000011, 0b0100011, 0b1100011, 0b1101111,
+      0b1100111, 0b0110111, 0b0010111, 0b1110011,
+    ];
+    let mut x: u32 = 0x2545f491;
+    for i in 0..20_000 {
+      // xorshift32
+      x ^= x << 13;
+      x ^= x >> 17;
+      x ^= x << 5;
+      // bias half of the samples onto the opcodes the fast path handles
+      let inst = if i % 2 == 0 {
+        x & !0x7f | HOT[(x >> 7) as usize % HOT.len()]
+      } else {
+        x
+      };
+      assert_eq!(decode_inst(inst), decode_table(inst), "0x{:08x}", inst);
+    }
+    // load funct3 = 111 is reserved
+    assert_eq!(decode_inst(0x0002f303), None);
+  }
+
+  #[test]
+  fn test_warm_reset_reruns_img() {
+    let mut boot = BootConfig::new();
+    boot.reset_pc = 0x80101100;
+    let cpu = &mut Cpu::with_boot_config(boot);
+    cpu.set_history_size(4);
+    cpu.set_trace_hash(true);
+    load_program(
+      cpu,
+      0x80101100,
+      &[
+        0x00000097, // auipc ra, 0
+        0x0200b603, // ld a2, 32(ra)
+        0x00160613, // addi a2, a2, 1
+        0x02c0b023, // sd a2, 32(ra)
+        0x00000513, // addi a0, zero, 0
+        0x00100073, // ebreak
+      ],
+    );
+    assert_eq!(cpu.exec(usize::MAX), 0);
+    assert_eq!(cpu.gpr[12], 1);
+
+    cpu.reset();
+    assert!(cpu.state == CpuState::Running);
+    assert_eq!((cpu.pc, cpu.gpr[12]), (0x80101100, 0));
+    assert_eq!(cpu.recent_history().count(), 0);
+    assert_eq!(cpu.statistic.count, 0);
+
+    // the program and the counter it stored are still in memory
+    assert_eq!(cpu.exec(usize::MAX), 0);
+    assert_eq!(cpu.gpr[12], 2);
+
+    // a cold cpu over the same memory retires the same run
+    write_data(0x80101120, 8, 1);
+    let cold = &mut Cpu::with_boot_config(boot);
+    cold.set_trace_hash(true);
+    assert_eq!(cold.exec(usize::MAX), 0);
+    assert_eq!(cpu.statistic.count, cold.statistic.count);
+    assert_eq!(cpu.trace_hash(), cold.trace_hash());
+  }
+
+  #[test]
+  fn test_exit_reason() {
+    let cpu = &mut Cpu::new();
+    load_program(
+      cpu,
+      0x80101200,
+      &[
+        0x00300513, // addi a0, zero, 3
+        0x00100073, // ebreak
+      ],
+    );
+    cpu.exec(1);
+    assert_eq!(cpu.exit_reason(), ExitReason::LimitReached);
+    cpu.exec(usize::MAX);
+    assert_eq!(cpu.exit_reason(), ExitReason::Halted(3));
+    assert_eq!(cpu.exit_reason().exit_code(), 3);
+
+    let cpu = &mut Cpu::new();
+    load_program(cpu, 0x80101210, &[0x00000000]); // unimp
+    cpu.exec(usize::MAX);
+    assert_eq!(cpu.exit_reason(), ExitReason::Aborted);
+    assert_ne!(cpu.exit_reason().exit_code(), 0);
+    assert_eq!(ExitReason::InitFailed.exit_code(), 1);
+    assert_ne!(ExitReason::Halted(256).exit_code(), 0);
+    assert_ne!(ExitReason::Halted(-256).exit_code(), 0);
+  }
+
+  #[test]
+  fn test_store_visible_in_pmem_slice() {
+    let cpu = &mut Cpu::new();
+    load_program(
+      cpu,
+      0x80101300,
+      &[
+        0x00000097, // auipc ra, 0
+        0xffff0137, // lui sp, 0xffff0
+        0x0220b023, // sd sp, 32(ra)
+      ],
+    );
+    cpu.exec(3);
+
+    let offset = (0x80101320 - crate::constants::MEM_BASE) as usize;
+    let bytes = with_pmem_slice(|mem| mem[offset..offset + 8].to_vec());
+    assert_eq!(bytes, 0xffff_ffff_ffff_0000u64.to_le_bytes());
+  }
+
+  // M extension division corner cases: zero divisors, signed overflow and
+  // the sign extension of 32-bit results
+  #[cfg(feature = "m")]
+  mod div {
+    use super::*;
+
+    const DIV: u32 = 0x0220c1b3; // div gp, ra, sp
+    const DIVU: u32 = 0x0220d1b3; // divu gp, ra, sp
+    const REM: u32 = 0x0220e1b3; // rem gp, ra, sp
+    const REMU: u32 = 0x0220f1b3; // remu gp, ra, sp
+    const DIVW: u32 = 0x0220c1bb; // divw gp, ra, sp
+    const DIVUW: u32 = 0x0220d1bb; // divuw gp, ra, sp
+    const REMW: u32 = 0x0220e1bb; // remw gp, ra, sp
+    const REMUW: u32 = 0x0220f1bb; // remuw gp, ra, sp
+
+    fn run(inst: u32, a: u64, b: u64) -> u64 {
+      let cpu = &mut Cpu::new();
+      cpu.gpr[1] = a;
+      cpu.gpr[2] = b;
+      exec_inst(cpu, inst);
+      cpu.gpr[3]
+    }
+
+    #[test]
+    fn test_div_rem() {
+      assert_eq!(run(DIV, -7i64 as u64, 2), -3i64 as u64);
+      assert_eq!(run(REM, -7i64 as u64, 2), -1i64 as u64);
+      assert_eq!(run(DIVU, u64::MAX, 2), u64::MAX >> 1);
+      assert_eq!(run(REMU, u64::MAX, 2), 1);
+    }
+
+    #[test]
+    fn test_div_by_zero() {
+      assert_eq!(run(DIV, 42, 0), u64::MAX);
+      assert_eq!(run(DIVU, 42, 0), u64::MAX);
+      assert_eq!(run(REM, -42i64 as u64, 0), -42i64 as u64);
+      assert_eq!(run(REMU, 42, 0), 42);
+    }
+
+    #[test]
+    fn test_div_overflow() {
+      assert_eq!(run(DIV, i64::MIN as u64, u64::MAX), i64::MIN as u64);
+      assert_eq!(run(REM, i64::MIN as u64, u64::MAX), 0);
+    }
+
+    #[test]
+    fn test_divw_truncates_operands() {
+      // only the low words take part, the upper halves are ignored
+      assert_eq!(
+        run(DIVW, 0xdead_beef_0000_0007, 0x1234_5678_ffff_fffe),
+        -3i64 as u64
+      );
+      assert_eq!(run(REMW, 0xdead_beef_0000_0007, 0x1234_5678_ffff_fffe), 1);
+      assert_eq!(
+        run(DIVUW, 0x1_ffff_fffe, 0x1_0000_0001),
+        0xffff_ffff_ffff_fffe
+      );
+      assert_eq!(run(REMUW, 0x1_8000_0005, 0x1_8000_0000), 5);
+    }
+
+    #[test]
+    fn test_divw_sign_extends() {
+      assert_eq!(run(DIVW, 0x8000_0000, 1), 0xffff_ffff_8000_0000);
+      assert_eq!(run(DIVUW, 0xffff_fffe, 1), 0xffff_ffff_ffff_fffe);
+      assert_eq!(run(REMW, 0xffff_fff9, 4), 0xffff_ffff_ffff_fffd);
+      assert_eq!(run(REMUW, 0xffff_fffe, 0xffff_ffff), 0xffff_ffff_ffff_fffe);
+    }
+
+    #[test]
+    fn test_divw_overflow() {
+      assert_eq!(
+        run(DIVW, i32::MIN as u64, u32::MAX as u64),
+        0xffff_ffff_8000_0000
+      );
+      assert_eq!(run(REMW, i32::MIN as u64, u32::MAX as u64), 0);
+    }
+
+    #[test]
+    fn test_divw_by_zero() {
+      // a divisor is zero when its low word is, whatever the upper half
+      assert_eq!(run(DIVW, 42, 0x1_0000_0000), u64::MAX);
+      assert_eq!(run(DIVUW, 42, 0), 0xffff_ffff_ffff_ffff);
+      assert_eq!(run(REMW, 0x8000_0000, 0), 0xffff_ffff_8000_0000);
+      assert_eq!(run(REMUW, 0x1_ffff_ffff, 0), 0xffff_ffff_ffff_ffff);
+    }
   }
 }
@@ -0,0 +1,96 @@
+use crate::cpu::decode_inst;
+use crate::cpu::instruction::{ImmediateType, Instruction};
+use crate::cpu::utils::decode_operand;
+
+#[rustfmt::skip]
+const REG_NAMES: [&str; 32] = [
+  "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2",
+  "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+  "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7",
+  "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
+
+pub fn mnemonic(inst_type: Instruction) -> String {
+  let name = match inst_type {
+    Instruction::Register(t) => format!("{:?}", t),
+    Instruction::Immediate(t) => format!("{:?}", t),
+    Instruction::Store(t) => format!("{:?}", t),
+    Instruction::Branch(t) => format!("{:?}", t),
+    Instruction::Jump(t) => format!("{:?}", t),
+    Instruction::Upper(t) => format!("{:?}", t),
+  };
+  name.to_lowercase()
+}
+
+// objdump-like operands, without pseudo-instruction aliases
+pub fn disassemble(pc: u64, inst: u32) -> String {
+  // all-zero and `csrrw zero, cycle, zero` are the encodings compilers emit for unimp
+  if inst == 0x00000000 || inst == 0xc0001073 {
+    return "unimp".to_string();
+  }
+  let inst_type = match decode_inst(inst) {
+    Some(inst_type) => inst_type,
+    None => return "unknown".to_string(),
+  };
+  let (rd, rs1, rs2, imm) = decode_operand(inst, inst_type);
+  let (rd, rs1, rs2) = (REG_NAMES[rd], REG_NAMES[rs1], REG_NAMES[rs2]);
+  let name = mnemonic(inst_type);
+  let target = (pc as i64).wrapping_add(imm) as u64;
+
+  match inst_type {
+    Instruction::Register(_) => format!("{}\t{},{},{}", name, rd, rs1, rs2),
+    Instruction::Immediate(t) => match t {
+      ImmediateType::ECALL | ImmediateType::EBREAK => name,
+      ImmediateType::LB
+      | ImmediateType::LH
+      | ImmediateType::LW
+      | ImmediateType::LD
+      | ImmediateType::LBU
+      | ImmediateType::LHU
+      | ImmediateType::LWU
+      | ImmediateType::JALR => format!("{}\t{},{}({})", name, rd, imm, rs1),
+      ImmediateType::SLLI | ImmediateType::SRLI | ImmediateType::SRAI => {
+        format!("{}\t{},{},0x{:x}", name, rd, rs1, imm & 0x3f)
+      }
+      #[cfg(feature = "zbs")]
+      ImmediateType::BCLRI
+      | ImmediateType::BEXTI
+      | ImmediateType::BINVI
+      | ImmediateType::BSETI => {
+        format!("{}\t{},{},0x{:x}", name, rd, rs1, imm & 0x3f)
+      }
+      ImmediateType::SLLIW | ImmediateType::SRLIW | ImmediateType::SRAIW => {
+        format!("{}\t{},{},0x{:x}", name, rd, rs1, imm & 0x1f)
+      }
+      _ => format!("{}\t{},{},{}", name, rd, rs1, imm),
+    },
+    Instruction::Store(_) => format!("{}\t{},{}({})", name, rs2, imm, rs1),
+    Instruction::Branch(_) => format!("{}\t{},{},{:x}", name, rs1, rs2, target),
+    Instruction::Jump(_) => format!("{}\t{},{:x}", name, rd, target),
+    Instruction::Upper(_) => {
+      format!("{}\t{},0x{:x}", name, rd, (imm >> 12) & 0xfffff)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_disassemble() {
+    assert_eq!(disassemble(0x80000000, 0x00000413), "addi\ts0,zero,0");
+    assert_eq!(disassemble(0x80000004, 0x00009117), "auipc\tsp,0x9");
+    assert_eq!(disassemble(0x80000008, 0xffc10113), "addi\tsp,sp,-4");
+    assert_eq!(disassemble(0x8000000c, 0x00c000ef), "jal\tra,80000018");
+    assert_eq!(disassemble(0x80000014, 0x00008067), "jalr\tzero,0(ra)");
+    assert_eq!(disassemble(0x80000024, 0x00113423), "sd\tra,8(sp)");
+    assert_eq!(disassemble(0x80000028, 0xfe9ff0ef), "jal\tra,80000010");
+    assert_eq!(disassemble(0x80000030, 0x00100073), "ebreak");
+    assert_eq!(disassemble(0x80000000, 0xfe0718e3), "bne\ta4,zero,7ffffff0");
+    assert_eq!(disassemble(0x80000000, 0x43f0d193), "srai\tgp,ra,0x3f");
+    assert_eq!(disassemble(0x80000000, 0x00000000), "unimp");
+    assert_eq!(disassemble(0x80000000, 0xc0001073), "unimp");
+    assert_eq!(disassemble(0x80000000, 0xffffffff), "unknown");
+  }
+}
@@ -0,0 +1,58 @@
+use crate::cpu::disasm::disassemble;
+use std::collections::VecDeque;
+use std::fmt;
+
+pub struct HistoryEntry {
+  pub pc: u64,
+  pub inst: u32,
+  // no memory backs pc, inst was never fetched
+  pub fetch_fault: bool,
+}
+
+impl fmt::Display for HistoryEntry {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.fetch_fault {
+      return write!(f, "0x{:016x}: fetch fault", self.pc);
+    }
+    write!(
+      f,
+      "0x{:016x}: 0x{:08x} {}",
+      self.pc,
+      self.inst,
+      disassemble(self.pc, self.inst)
+    )
+  }
+}
+
+// ring buffer of the last retired instructions, disabled when capacity is 0
+pub struct History {
+  capacity: usize,
+  entries: VecDeque<HistoryEntry>,
+}
+
+impl History {
+  pub fn new(capacity: usize) -> History {
+    History {
+      capacity,
+      entries: VecDeque::with_capacity(capacity),
+    }
+  }
+
+  pub fn push(&mut self, entry: HistoryEntry) {
+    if self.capacity == 0 {
+      return;
+    }
+    if self.entries.len() == self.capacity {
+      self.entries.pop_front();
+    }
+    self.entries.push_back(entry);
+  }
+
+  pub fn clear(&mut self) {
+    self.entries.clear();
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &HistoryEntry> {
+    self.entries.iter()
+  }
+}
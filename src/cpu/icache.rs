@@ -0,0 +1,64 @@
+// set-associative instruction fetch cache with LRU replacement, only the
+// hit/miss sequence is modeled for comparing against the DUT perf counters,
+// fetched instructions always come from memory
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ICacheConfig {
+  pub sets: u64,
+  pub ways: usize,
+  pub line_size: u64,
+}
+
+pub struct ICache {
+  config: ICacheConfig,
+  // line tags of every set, most recently used last
+  tags: Vec<Vec<u64>>,
+  pub hits: u64,
+  pub misses: u64,
+}
+
+impl ICache {
+  pub fn new(config: ICacheConfig) -> ICache {
+    assert!(
+      config.sets > 0 && config.ways > 0 && config.line_size > 0,
+      "icache geometry must be non-zero"
+    );
+    ICache {
+      config,
+      tags: vec![Vec::with_capacity(config.ways); config.sets as usize],
+      hits: 0,
+      misses: 0,
+    }
+  }
+
+  pub fn clear(&mut self) {
+    self.tags.iter_mut().for_each(Vec::clear);
+    self.hits = 0;
+    self.misses = 0;
+  }
+
+  // look up the line holding `addr` and fill it on a miss, true on a hit
+  pub fn access(&mut self, addr: u64) -> bool {
+    let line = addr / self.config.line_size;
+    let set = &mut self.tags[(line % self.config.sets) as usize];
+    let tag = line / self.config.sets;
+    let hit = match set.iter().position(|&t| t == tag) {
+      Some(way) => {
+        set.remove(way);
+        true
+      }
+      None => {
+        if set.len() == self.config.ways {
+          set.remove(0);
+        }
+        false
+      }
+    };
+    set.push(tag);
+    if hit {
+      self.hits += 1;
+    } else {
+      self.misses += 1;
+    }
+    hit
+  }
+}
@@ -1,4 +1,7 @@
-#[derive(Copy, Clone, Debug)]
+// enum variants are named after the ISA mnemonics
+#![allow(clippy::upper_case_acronyms)]
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Instruction {
   Register(RegisterType),
   Immediate(ImmediateType),
@@ -8,7 +11,7 @@ pub enum Instruction {
   Upper(UpperType),
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum RegisterType {
   ADD,
   SUB,
@@ -17,18 +20,49 @@ pub enum RegisterType {
   AND,
   SLL,
   SRL,
-  // SRA, // todo
+  SRA,
   SLT,
   SLTU,
+  #[cfg(feature = "m")]
   MUL,
+  #[cfg(feature = "m")]
+  MULH,
+  #[cfg(feature = "m")]
+  MULHSU,
+  #[cfg(feature = "m")]
+  MULHU,
+  #[cfg(feature = "m")]
   MULW,
+  #[cfg(feature = "m")]
   DIV,
+  #[cfg(feature = "m")]
   DIVU,
+  #[cfg(feature = "m")]
   REM,
+  #[cfg(feature = "m")]
   REMU,
+  #[cfg(feature = "m")]
+  DIVW,
+  #[cfg(feature = "m")]
+  DIVUW,
+  #[cfg(feature = "m")]
+  REMW,
+  #[cfg(feature = "m")]
+  REMUW,
+  SLLW,
+  SRLW,
+  SRAW,
+  #[cfg(feature = "zbs")]
+  BCLR,
+  #[cfg(feature = "zbs")]
+  BEXT,
+  #[cfg(feature = "zbs")]
+  BINV,
+  #[cfg(feature = "zbs")]
+  BSET,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum ImmediateType {
   ADDI,
   XORI,
@@ -46,13 +80,23 @@ pub enum ImmediateType {
   LBU,
   LHU,
   LWU,
-  LDU,
   JALR,
   ECALL,
   EBREAK,
+  SLLIW,
+  SRLIW,
+  SRAIW,
+  #[cfg(feature = "zbs")]
+  BCLRI,
+  #[cfg(feature = "zbs")]
+  BEXTI,
+  #[cfg(feature = "zbs")]
+  BINVI,
+  #[cfg(feature = "zbs")]
+  BSETI,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum StoreType {
   SB,
   SH,
@@ -61,7 +105,7 @@ pub enum StoreType {
 }
 
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum BranchType {
   BEQ,
   BNE,
@@ -71,12 +115,12 @@ pub enum BranchType {
   BGEU,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum JumpType {
   JAL,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum UpperType {
   LUI,
   AUIPC,
@@ -88,7 +132,7 @@ pub struct InstPattern {
 }
 
 impl InstPattern {
-  pub fn new(pattern: &'static str, itype: Instruction) -> InstPattern {
+  pub const fn new(pattern: &'static str, itype: Instruction) -> InstPattern {
     InstPattern { pattern, itype }
   }
 }
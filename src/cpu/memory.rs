@@ -1,7 +1,10 @@
 use crate::memory::vaddr;
+use std::collections::VecDeque;
+use std::fmt;
 
-pub fn read_inst(addr: u64) -> u64 {
-  read_data(addr, 4)
+// None when no memory backs the pc
+pub fn read_inst(addr: u64) -> Option<u64> {
+  try_read_data(addr, 4)
 }
 
 #[allow(dead_code)]
@@ -9,6 +12,102 @@ pub fn read_data(addr: u64, len: i32) -> u64 {
   vaddr::vaddr_read(addr, len)
 }
 
+#[allow(dead_code)]
 pub fn write_data(addr: u64, len: i32, data: u64) {
   vaddr::vaddr_write(addr, len, data)
-}
\ No newline at end of file
+}
+
+// guest accesses go through these, a bad address is the cpu's to report
+pub fn try_read_data(addr: u64, len: i32) -> Option<u64> {
+  vaddr::vaddr_try_read(addr, len)
+}
+
+pub fn try_write_data(addr: u64, len: i32, data: u64) -> bool {
+  vaddr::vaddr_try_write(addr, len, data)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AccessKind {
+  Read,
+  Write,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MemAccess {
+  pub kind: AccessKind,
+  pub addr: u64,
+  pub len: i32,
+  pub data: u64,
+}
+
+impl fmt::Display for MemAccess {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let kind = match self.kind {
+      AccessKind::Read => "read ",
+      AccessKind::Write => "write",
+    };
+    write!(
+      f,
+      "{} 0x{:016x} [{}] = 0x{:016x}",
+      kind, self.addr, self.len, self.data
+    )
+  }
+}
+
+// data accesses since the last exec call started (one sdb `s` or `c`),
+// keeps at most `depth` of the latest ones
+pub struct MemRecord {
+  depth: usize,
+  accesses: VecDeque<MemAccess>,
+}
+
+impl MemRecord {
+  pub fn new(depth: usize) -> MemRecord {
+    MemRecord {
+      depth,
+      accesses: VecDeque::with_capacity(depth),
+    }
+  }
+
+  pub fn push(&mut self, access: MemAccess) {
+    if self.depth == 0 {
+      return;
+    }
+    if self.accesses.len() == self.depth {
+      self.accesses.pop_front();
+    }
+    self.accesses.push_back(access);
+  }
+
+  pub fn clear(&mut self) {
+    self.accesses.clear();
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &MemAccess> {
+    self.accesses.iter()
+  }
+
+  // the recorded accesses with runs of stores to adjacent ascending
+  // addresses merged, up to `window` bytes each, for comparing against
+  // hardware that combines narrow writes; the record itself is unchanged
+  pub fn coalesced(&self, window: i32) -> Vec<MemAccess> {
+    // a merged store still has to fit the 64-bit data field
+    let window = window.min(8);
+    let mut merged: Vec<MemAccess> = Vec::new();
+    for access in self.accesses.iter() {
+      if let Some(last) = merged.last_mut() {
+        if last.kind == AccessKind::Write
+          && access.kind == AccessKind::Write
+          && access.addr == last.addr + last.len as u64
+          && last.len + access.len <= window
+        {
+          last.data |= access.data << (last.len * 8);
+          last.len += access.len;
+          continue;
+        }
+      }
+      merged.push(*access);
+    }
+    merged
+  }
+}
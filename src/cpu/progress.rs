@@ -0,0 +1,43 @@
+use crate::cpu::memory::AccessKind;
+use crate::cpu::retire::RetireRecord;
+use std::collections::HashMap;
+
+// flags guest loops that keep revisiting a pc without writing memory or
+// changing a register, e.g. `j .` or polling a value that never changes
+pub struct ProgressMonitor {
+  limit: u64,
+  gpr: [u64; 32],
+  visits: HashMap<u64, u64>,
+}
+
+impl ProgressMonitor {
+  pub fn new(limit: u64) -> ProgressMonitor {
+    ProgressMonitor {
+      limit,
+      gpr: [0; 32],
+      visits: HashMap::new(),
+    }
+  }
+
+  pub fn clear(&mut self) {
+    self.gpr = [0; 32];
+    self.visits.clear();
+  }
+
+  // true once some pc has retired `limit` times since the last progress
+  pub fn stalled(&mut self, record: &RetireRecord) -> bool {
+    let wrote_mem =
+      matches!(record.mem, Some(access) if access.kind == AccessKind::Write);
+    let changed_reg = match record.rd {
+      Some((rd, value)) => std::mem::replace(&mut self.gpr[rd], value) != value,
+      None => false,
+    };
+    if wrote_mem || changed_reg {
+      self.visits.clear();
+      return false;
+    }
+    let visits = self.visits.entry(record.pc).or_insert(0);
+    *visits += 1;
+    *visits >= self.limit
+  }
+}
@@ -0,0 +1,137 @@
+use crate::cpu::instruction::{ImmediateType, Instruction};
+use crate::cpu::memory::MemAccess;
+use std::fmt;
+
+// everything one step changed, for embedders that trace or compare runs
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RetireRecord {
+  pub pc: u64,
+  pub next_pc: u64,
+  pub inst: u32,
+  // destination register and its new value, None if nothing was written
+  pub rd: Option<(usize, u64)>,
+  // the data access of a load or store
+  pub mem: Option<MemAccess>,
+}
+
+// x0 writes are discarded, so they are not reported either
+pub fn writes_rd(inst_type: Instruction, rd: usize) -> bool {
+  if rd == 0 {
+    return false;
+  }
+  match inst_type {
+    Instruction::Register(_) | Instruction::Jump(_) | Instruction::Upper(_) => {
+      true
+    }
+    Instruction::Immediate(t) => {
+      !matches!(t, ImmediateType::ECALL | ImmediateType::EBREAK)
+    }
+    Instruction::Store(_) | Instruction::Branch(_) => false,
+  }
+}
+
+// one line per retirement, the format of --retire-trace files
+impl fmt::Display for RetireRecord {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "0x{:016x} 0x{:08x} -> 0x{:016x}",
+      self.pc, self.inst, self.next_pc
+    )?;
+    if let Some((rd, value)) = self.rd {
+      write!(f, " x{}=0x{:016x}", rd, value)?;
+    }
+    if let Some(access) = self.mem {
+      write!(f, " {}", access)?;
+    }
+    Ok(())
+  }
+}
+
+// FNV-1a over every retirement, so a whole run collapses to one number
+pub struct TraceHash(u64);
+
+impl TraceHash {
+  pub fn new() -> TraceHash {
+    TraceHash(0xcbf29ce484222325)
+  }
+
+  fn mix(&mut self, value: u64) {
+    for byte in value.to_le_bytes() {
+      self.0 = (self.0 ^ byte as u64).wrapping_mul(0x100000001b3);
+    }
+  }
+
+  pub fn update(&mut self, record: &RetireRecord) {
+    self.mix(record.pc);
+    if let Some((rd, value)) = record.rd {
+      self.mix(rd as u64);
+      self.mix(value);
+    }
+    if let Some(access) = record.mem {
+      self.mix(access.kind as u64);
+      self.mix(access.addr);
+      self.mix(access.len as u64);
+      self.mix(access.data);
+    }
+  }
+
+  pub fn value(&self) -> u64 {
+    self.0
+  }
+}
+
+// first retirement where two traces disagree; a missing line means that
+// trace ended earlier
+#[derive(Debug, PartialEq)]
+pub struct TraceDiff<'a> {
+  pub index: usize,
+  pub a: Option<&'a str>,
+  pub b: Option<&'a str>,
+}
+
+pub fn diff_traces<'a>(a: &'a str, b: &'a str) -> Option<TraceDiff<'a>> {
+  let (mut a, mut b) = (a.lines(), b.lines());
+  let mut index = 0;
+  loop {
+    match (a.next(), b.next()) {
+      (None, None) => return None,
+      (la, lb) if la != lb => {
+        return Some(TraceDiff {
+          index,
+          a: la,
+          b: lb,
+        })
+      }
+      _ => index += 1,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const TRACE: &str = "\
+0x0000000080000000 0x00000413 -> 0x0000000080000004 x8=0x0000000000000000
+0x0000000080000004 0x00009117 -> 0x0000000080000008 x2=0x0000000080009004
+0x0000000080000008 0x00100073 -> 0x000000008000000c
+";
+
+  #[test]
+  fn test_diff_traces() {
+    assert_eq!(diff_traces(TRACE, TRACE), None);
+
+    let diverged =
+      TRACE.replace("x2=0x0000000080009004", "x2=0x0000000080009000");
+    let diff = diff_traces(TRACE, &diverged).unwrap();
+    assert_eq!(diff.index, 1);
+    assert!(diff.a.unwrap().ends_with("x2=0x0000000080009004"));
+    assert!(diff.b.unwrap().ends_with("x2=0x0000000080009000"));
+
+    let truncated: String =
+      TRACE.lines().take(2).map(|l| format!("{}\n", l)).collect();
+    let diff = diff_traces(TRACE, &truncated).unwrap();
+    assert_eq!((diff.index, diff.b), (2, None));
+  }
+}
@@ -0,0 +1,206 @@
+use crate::cpu::Cpu;
+use crate::memory::paddr::{memory_regions, with_paddr_bytes_mut};
+use std::error::Error;
+use std::io::{Read, Write};
+
+const MAGIC: &[u8; 8] = b"HEMUSNAP";
+const VERSION: u32 = 1;
+const PAGE_SIZE: u64 = 0x1000;
+
+// architectural state plus the contents of every ram region, only pages
+// holding a non-zero byte are stored to keep repro files small
+//
+// file layout, all integers little-endian:
+//   magic "HEMUSNAP", version u32, pc u64, gpr [u64; 32],
+//   region count u32, [start u64, end u64] per region,
+//   page count u32, [addr u64, len u32, bytes] per page
+pub struct Snapshot {
+  pub pc: u64,
+  pub gpr: [u64; 32],
+  // [start, end) ranges restored as a whole, page bytes outside them are ignored
+  pub regions: Vec<(u64, u64)>,
+  pub pages: Vec<(u64, Vec<u8>)>,
+}
+
+impl Snapshot {
+  pub fn capture(cpu: &Cpu) -> Snapshot {
+    let regions: Vec<(u64, u64)> = memory_regions()
+      .iter()
+      .map(|&(start, end, _)| (start, end))
+      .collect();
+    let mut pages = Vec::new();
+    for &(start, end) in regions.iter() {
+      for addr in (start..end).step_by(PAGE_SIZE as usize) {
+        let len = (end - addr).min(PAGE_SIZE) as usize;
+        let page =
+          with_paddr_bytes_mut(addr, len, |bytes| bytes.to_vec()).unwrap();
+        if page.iter().any(|&b| b != 0) {
+          pages.push((addr, page));
+        }
+      }
+    }
+    Snapshot {
+      pc: cpu.pc,
+      gpr: cpu.gpr,
+      regions,
+      pages,
+    }
+  }
+
+  // warm reset `cpu` into the captured state, every page of the captured
+  // regions is rewritten so memory matches no matter what ran before
+  pub fn restore(&self, cpu: &mut Cpu) -> Result<(), Box<dyn Error>> {
+    self.check()?;
+    for &(start, end) in self.regions.iter() {
+      if with_paddr_bytes_mut(start, (end - start) as usize, |_| ()).is_none() {
+        return Err(
+          format!("snapshot region [0x{:x}, 0x{:x}) is not mapped", start, end)
+            .into(),
+        );
+      }
+    }
+    for &(start, end) in self.regions.iter() {
+      with_paddr_bytes_mut(start, (end - start) as usize, |bytes| {
+        bytes.fill(0)
+      });
+    }
+    for (addr, page) in self.pages.iter() {
+      for &(start, end) in self.regions.iter() {
+        let lo = (*addr).max(start);
+        let hi = (addr + page.len() as u64).min(end);
+        if lo < hi {
+          let part = &page[(lo - addr) as usize..(hi - addr) as usize];
+          with_paddr_bytes_mut(lo, part.len(), |bytes| {
+            bytes.copy_from_slice(part)
+          });
+        }
+      }
+    }
+    cpu.reset();
+    cpu.gpr = self.gpr;
+    cpu.pc = self.pc;
+    cpu.snpc = self.pc;
+    cpu.dnpc = self.pc;
+    Ok(())
+  }
+
+  // reject ranges a corrupt or crafted file could use to underflow or
+  // overflow the restore arithmetic
+  fn check(&self) -> Result<(), Box<dyn Error>> {
+    for &(start, end) in self.regions.iter() {
+      if start > end {
+        return Err(
+          format!(
+            "snapshot region [0x{:x}, 0x{:x}) ends before it starts",
+            start, end
+          )
+          .into(),
+        );
+      }
+    }
+    for (addr, page) in self.pages.iter() {
+      if page.len() as u64 > PAGE_SIZE {
+        return Err(
+          format!(
+            "snapshot page at 0x{:x} is larger than 0x{:x} bytes",
+            addr, PAGE_SIZE
+          )
+          .into(),
+        );
+      }
+      if addr.checked_add(page.len() as u64).is_none() {
+        return Err(
+          format!("snapshot page at 0x{:x} wraps the address space", addr)
+            .into(),
+        );
+      }
+    }
+    Ok(())
+  }
+
+  pub fn write_to(&self, out: &mut impl Write) -> std::io::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&VERSION.to_le_bytes())?;
+    out.write_all(&self.pc.to_le_bytes())?;
+    for reg in self.gpr.iter() {
+      out.write_all(&reg.to_le_bytes())?;
+    }
+    out.write_all(&(self.regions.len() as u32).to_le_bytes())?;
+    for (start, end) in self.regions.iter() {
+      out.write_all(&start.to_le_bytes())?;
+      out.write_all(&end.to_le_bytes())?;
+    }
+    out.write_all(&(self.pages.len() as u32).to_le_bytes())?;
+    for (addr, page) in self.pages.iter() {
+      out.write_all(&addr.to_le_bytes())?;
+      out.write_all(&(page.len() as u32).to_le_bytes())?;
+      out.write_all(page)?;
+    }
+    Ok(())
+  }
+
+  pub fn read_from(input: &mut impl Read) -> Result<Snapshot, Box<dyn Error>> {
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+      return Err("not a hemu snapshot".into());
+    }
+    let version = read_u32(input)?;
+    if version != VERSION {
+      return Err(
+        format!(
+          "unsupported snapshot version {}, expected {}",
+          version, VERSION
+        )
+        .into(),
+      );
+    }
+    let pc = read_u64(input)?;
+    let mut gpr = [0; 32];
+    for reg in gpr.iter_mut() {
+      *reg = read_u64(input)?;
+    }
+    let mut regions = Vec::new();
+    for _ in 0..read_u32(input)? {
+      regions.push((read_u64(input)?, read_u64(input)?));
+    }
+    let mut pages = Vec::new();
+    for _ in 0..read_u32(input)? {
+      let addr = read_u64(input)?;
+      // checked before allocating, the length comes straight from the file
+      let len = read_u32(input)?;
+      if len as u64 > PAGE_SIZE {
+        return Err(
+          format!(
+            "snapshot page at 0x{:x} is larger than 0x{:x} bytes",
+            addr, PAGE_SIZE
+          )
+          .into(),
+        );
+      }
+      let mut page = vec![0; len as usize];
+      input.read_exact(&mut page)?;
+      pages.push((addr, page));
+    }
+    let snapshot = Snapshot {
+      pc,
+      gpr,
+      regions,
+      pages,
+    };
+    snapshot.check()?;
+    Ok(snapshot)
+  }
+}
+
+fn read_u32(input: &mut impl Read) -> std::io::Result<u32> {
+  let mut bytes = [0; 4];
+  input.read_exact(&mut bytes)?;
+  Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(input: &mut impl Read) -> std::io::Result<u64> {
+  let mut bytes = [0; 8];
+  input.read_exact(&mut bytes)?;
+  Ok(u64::from_le_bytes(bytes))
+}
@@ -7,7 +7,7 @@ pub fn match_inst(inst: u32, pattern: &str) -> bool {
   for (i, c) in pattern.replace(" ", "").chars().enumerate() {
     if c == '0' || c == '1' {
       mask |= 1 << (31 - i);
-      expected |= (c.to_digit(2).unwrap() as u32) << (31 - i);
+      expected |= c.to_digit(2).unwrap() << (31 - i);
     }
   }
 
@@ -1,10 +1,62 @@
+use crate::constants::MEM_BASE;
+use crate::cpu::{BootConfig, Cpu, ExitReason};
+use crate::memory::paddr::pmem_size;
 use crate::monitor::{init_monitor, sdb};
-use crate::cpu::Cpu;
 
-pub fn engine_start() {
-  let _ = init_monitor();
+pub fn engine_start() -> ExitReason {
+  let args = match init_monitor() {
+    Ok(args) => args,
+    Err(err) => {
+      // the logger may not be up yet, e.g. for a bad --config
+      eprintln!("hemu: {}", err);
+      return ExitReason::InitFailed;
+    }
+  };
+  if args.disasm.is_some() || args.diff_traces.is_some() || args.dump_isa {
+    return ExitReason::UserQuit;
+  }
 
-  let cpu = &mut Cpu::new();
+  let mut boot = BootConfig::new();
+  boot.reset_pc = args.reset_pc;
+  boot.stack_top = MEM_BASE + pmem_size();
 
-  sdb::sdb_mainloop(cpu);
+  let cpu = &mut Cpu::with_boot_config(boot);
+  cpu.set_history_size(args.history);
+  cpu.set_count_only(args.count_only);
+  cpu.set_record_depth(args.record_depth);
+  cpu.set_progress_limit(args.progress_limit);
+  cpu.set_trace_hash(args.trace_hash);
+  cpu.set_icache(args.icache);
+  if let Some(path) = &args.retire_trace {
+    let file = match std::fs::File::create(path) {
+      Ok(file) => file,
+      Err(err) => {
+        eprintln!(
+          "hemu: failed to create retire trace {}: {}",
+          path.display(),
+          err
+        );
+        return ExitReason::InitFailed;
+      }
+    };
+    cpu.set_retire_trace(Box::new(std::io::BufWriter::new(file)));
+    cpu.set_trace_regs(args.trace_regs);
+  }
+  if let Some(path) = &args.load_state {
+    if let Err(err) = cpu.load_state(path) {
+      eprintln!("hemu: failed to load state {}: {}", path.display(), err);
+      return ExitReason::InitFailed;
+    }
+  }
+
+  if args.batch {
+    cpu.exec(usize::MAX);
+  } else {
+    sdb::sdb_mainloop(cpu);
+  }
+  // reported however the run ended, a diverging run is what it is for
+  if let Some(hash) = cpu.trace_hash() {
+    log::info!("trace hash = 0x{:016x}", hash);
+  }
+  cpu.exit_reason()
 }
@@ -1,6 +1,43 @@
+use clap::ValueEnum;
+use log::kv::{Error, Key, Value, Visitor};
+use serde_json::{Map, Value as Json};
 use std::env;
+use std::io::Write;
 
-pub fn init_log() {
+#[derive(Copy, Clone, Debug, PartialEq, ValueEnum)]
+pub enum LogFormat {
+  Text,
+  Json,
+}
+
+// collect the structured key-values of a record (e.g. pc, inst) as json fields
+struct JsonVisitor<'a>(&'a mut Map<String, Json>);
+
+impl<'kvs> Visitor<'kvs> for JsonVisitor<'_> {
+  fn visit_pair(
+    &mut self,
+    key: Key<'kvs>,
+    value: Value<'kvs>,
+  ) -> Result<(), Error> {
+    let value = match value.to_u64() {
+      Some(n) => Json::from(n),
+      None => Json::from(value.to_string()),
+    };
+    self.0.insert(key.to_string(), value);
+    Ok(())
+  }
+}
+
+pub fn json_record(record: &log::Record) -> Json {
+  let mut fields = Map::new();
+  fields.insert("level".to_string(), Json::from(record.level().as_str()));
+  fields.insert("target".to_string(), Json::from(record.target()));
+  fields.insert("message".to_string(), Json::from(record.args().to_string()));
+  let _ = record.key_values().visit(&mut JsonVisitor(&mut fields));
+  Json::Object(fields)
+}
+
+pub fn init_log(format: LogFormat) {
   let log_level = env::var("LOG_LEVEL").unwrap_or_else(|_| "info".to_string());
 
   let filter_level = match log_level.as_str() {
@@ -13,5 +50,34 @@ pub fn init_log() {
   };
   env::set_var("RUST_LOG", log_level);
 
-  env_logger::builder().filter_level(filter_level).init();
+  let mut builder = env_logger::builder();
+  builder.filter_level(filter_level);
+  if format == LogFormat::Json {
+    builder.format(|buf, record| writeln!(buf, "{}", json_record(record)));
+  }
+  builder.init();
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_json_record() {
+    let kvs: &[(&str, u64)] = &[("pc", 0x80000000), ("inst", 0x00100073)];
+    let args = format_args!("fetch");
+    let record = log::Record::builder()
+      .level(log::Level::Debug)
+      .target("hemu::cpu")
+      .args(args)
+      .key_values(&kvs)
+      .build();
+
+    let line = json_record(&record).to_string();
+    let parsed: Json = serde_json::from_str(&line).unwrap();
+    assert_eq!(parsed["level"], "DEBUG");
+    assert_eq!(parsed["message"], "fetch");
+    assert_eq!(parsed["pc"], 0x80000000u64);
+    assert_eq!(parsed["inst"], 0x00100073u64);
+  }
 }
@@ -8,5 +8,5 @@ mod constants;
 use engine::init::engine_start;
 
 fn main() {
-    engine_start();
+  std::process::exit(engine_start().exit_code());
 }
@@ -4,58 +4,356 @@ use lazy_static::lazy_static;
 use std::sync::Mutex;
 use crate::memory::host::{host_read, host_write};
 
+// additional RAM outside the main pmem window, e.g. a scratchpad
+struct RamRegion {
+  base: u64,
+  mem: Vec<u8>,
+}
+
+impl RamRegion {
+  fn contains(&self, addr: u64, len: i32) -> bool {
+    let offset = addr.wrapping_sub(self.base);
+    let size = self.mem.len() as u64;
+    offset < size && size - offset >= len as u64
+  }
+}
+
+pub struct Pmem {
+  mem: Vec<u8>,
+  extra: Vec<RamRegion>,
+}
+
+impl Pmem {
+  pub fn new(size: u64) -> Pmem {
+    Pmem {
+      mem: vec![0; size as usize],
+      extra: Vec::new(),
+    }
+  }
+
+  // back [base, base + size) with its own zeroed buffer
+  pub fn add_region(&mut self, base: u64, size: u64) -> Result<(), String> {
+    if size == 0 || size > MEM_SIZE_MAX {
+      return Err(format!(
+        "ram region size 0x{:x} is not in (0, 0x{:x}]",
+        size, MEM_SIZE_MAX
+      ));
+    }
+    let end = base
+      .checked_add(size)
+      .ok_or(format!("ram region at 0x{:x} overflows", base))?;
+    if self
+      .regions()
+      .iter()
+      .any(|&(start, stop, _)| base < stop && end > start)
+    {
+      return Err(format!(
+        "ram region [0x{:x}, 0x{:x}) overlaps another region",
+        base, end
+      ));
+    }
+    self.extra.push(RamRegion {
+      base,
+      mem: vec![0; size as usize],
+    });
+    Ok(())
+  }
+
+  pub fn regions(&self) -> Vec<(u64, u64, RegionKind)> {
+    let mut regions = vec![(MEM_BASE, MEM_BASE + self.size(), RegionKind::Ram)];
+    for region in self.extra.iter() {
+      regions.push((
+        region.base,
+        region.base + region.mem.len() as u64,
+        RegionKind::Ram,
+      ));
+    }
+    regions
+  }
+
+  pub fn size(&self) -> u64 {
+    self.mem.len() as u64
+  }
+
+  // the main pmem window, byte i is physical address MEM_BASE + i
+  pub fn as_slice(&self) -> &[u8] {
+    &self.mem
+  }
+
+  // pointers handed out by guest_to_host are invalid after a resize
+  pub fn resize(&mut self, size: u64) -> Result<(), String> {
+    if size == 0 || size > MEM_SIZE_MAX {
+      return Err(format!(
+        "memory size 0x{:x} is not in (0, 0x{:x}]",
+        size, MEM_SIZE_MAX
+      ));
+    }
+    let end = MEM_BASE
+      .checked_add(size)
+      .ok_or(format!("memory size 0x{:x} overflows", size))?;
+    if self.extra.iter().any(|region| {
+      region.base < end && region.base + region.mem.len() as u64 > MEM_BASE
+    }) {
+      return Err(format!(
+        "memory [0x{:x}, 0x{:x}) overlaps a ram region",
+        MEM_BASE, end
+      ));
+    }
+    self.mem.resize(size as usize, 0);
+    Ok(())
+  }
+
+  fn guest_to_host(&mut self, paddr: u64) -> *mut u8 {
+    (self.mem.as_mut_ptr() as u64)
+      .wrapping_add(paddr)
+      .wrapping_sub(MEM_BASE) as *mut u8
+  }
+
+  fn host_to_guest(&mut self, haddr: *mut u8) -> u64 {
+    (haddr as u64)
+      .wrapping_sub(self.mem.as_mut_ptr() as u64)
+      .wrapping_add(MEM_BASE)
+  }
+
+  // the whole access [addr, addr + len) has to be backed by pmem
+  fn in_pmem(&self, addr: u64, len: i32) -> bool {
+    let offset = addr.wrapping_sub(MEM_BASE);
+    offset < self.size() && self.size() - offset >= len as u64
+  }
+
+  // host address backing the whole access, None if no region holds it
+  fn host_addr(&mut self, addr: u64, len: i32) -> Option<u64> {
+    if self.in_pmem(addr, len) {
+      return Some(self.guest_to_host(addr) as u64);
+    }
+    self
+      .extra
+      .iter_mut()
+      .find(|region| region.contains(addr, len))
+      .map(|region| region.mem.as_mut_ptr() as u64 + (addr - region.base))
+  }
+
+  // the bytes backing [addr, addr + len), None unless one region holds all
+  // of them
+  pub fn bytes_mut(&mut self, addr: u64, len: usize) -> Option<&mut [u8]> {
+    let holds = |base: u64, size: usize| {
+      let offset = addr.wrapping_sub(base);
+      offset < size as u64 && size as u64 - offset >= len as u64
+    };
+    if holds(MEM_BASE, self.mem.len()) {
+      let offset = (addr - MEM_BASE) as usize;
+      return Some(&mut self.mem[offset..offset + len]);
+    }
+    self
+      .extra
+      .iter_mut()
+      .find(|region| holds(region.base, region.mem.len()))
+      .map(|region| {
+        let offset = (addr - region.base) as usize;
+        &mut region.mem[offset..offset + len]
+      })
+  }
+
+  fn out_of_bound(&self, addr: u64) -> ! {
+    panic!(
+      "address = {:016X} is out of bound of pmem [{:016X}, {:016X}) at pc",
+      addr,
+      MEM_BASE,
+      MEM_BASE + self.size(),
+    );
+  }
+
+  pub fn read(&mut self, addr: u64, len: i32) -> u64 {
+    match self.try_read(addr, len) {
+      Some(data) => data,
+      None => self.out_of_bound(addr),
+    }
+  }
+
+  pub fn write(&mut self, addr: u64, len: i32, data: u64) {
+    if !self.try_write(addr, len, data) {
+      self.out_of_bound(addr);
+    }
+  }
+
+  // like read, None instead of a panic when no region backs the access
+  pub fn try_read(&mut self, addr: u64, len: i32) -> Option<u64> {
+    self.host_addr(addr, len).map(|haddr| host_read(haddr, len))
+  }
+
+  // like write, false instead of a panic when no region backs the access
+  pub fn try_write(&mut self, addr: u64, len: i32, data: u64) -> bool {
+    self
+      .host_addr(addr, len)
+      .map(|haddr| host_write(haddr, len, data))
+      .is_some()
+  }
+}
+
 lazy_static! {
-  #[repr(align(4096))]
-  static ref PMEM: Mutex<Vec<u8>> = Mutex::new(vec![0; MEM_SIZE as usize]);
+  static ref PMEM: Mutex<Pmem> = Mutex::new(Pmem::new(MEM_SIZE));
 }
 
-pub fn guest_to_host(paddr: u64) -> *mut u8 {
-  let mut pmem = PMEM.lock().unwrap();
-  (pmem.as_mut_ptr() as u64)
-    .wrapping_add(paddr)
-    .wrapping_sub(MEM_BASE) as *mut u8
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RegionKind {
+  Ram,
 }
 
-#[allow(dead_code)]
-fn host_to_guest(haddr: *mut u8) -> u64 {
-  let mut pmem = PMEM.lock().unwrap();
-  (haddr as u64)
-    .wrapping_sub(pmem.as_mut_ptr() as u64)
-    .wrapping_add(MEM_BASE)
+// [start, end) ranges a debugger can inspect without faulting
+pub fn memory_regions() -> Vec<(u64, u64, RegionKind)> {
+  PMEM.lock().unwrap().regions()
 }
 
-#[allow(dead_code)]
-fn in_pmem(addr: u64) -> bool {
-  addr.wrapping_sub(MEM_BASE) < MEM_SIZE
+pub fn add_ram_region(base: u64, size: u64) -> Result<(), String> {
+  PMEM.lock().unwrap().add_region(base, size)
+}
+
+pub fn pmem_size() -> u64 {
+  PMEM.lock().unwrap().size()
 }
 
-fn out_of_bound(addr: u64) -> ! {
-  panic!(
-    "address = {:016X} is out of bound of pmem [{:016X}, {:016X}) at pc",
-    addr,
-    MEM_BASE,
-    MEM_BASE + MEM_SIZE,
-  );
+pub fn set_pmem_size(size: u64) -> Result<(), String> {
+  PMEM.lock().unwrap().resize(size)
 }
 
-pub fn pmem_read(addr: u64, len: i32) -> u64 {
-  host_read(guest_to_host(addr) as u64, len)
+// run `f` on the whole pmem window (indexed by paddr - MEM_BASE) while it
+// is locked, instead of going through paddr_read byte by byte
+#[allow(dead_code)]
+pub fn with_pmem_slice<R>(f: impl FnOnce(&[u8]) -> R) -> R {
+  f(PMEM.lock().unwrap().as_slice())
 }
 
-pub fn pmem_write(addr: u64, len: i32, data: u64) {
-  host_write(guest_to_host(addr) as u64, len, data)
+// run `f` on the bytes backing [addr, addr + len) in any region, None if
+// the range is not backed by a single region
+pub fn with_paddr_bytes_mut<R>(
+  addr: u64,
+  len: usize,
+  f: impl FnOnce(&mut [u8]) -> R,
+) -> Option<R> {
+  PMEM.lock().unwrap().bytes_mut(addr, len).map(f)
+}
+
+#[allow(dead_code)]
+fn host_to_guest(haddr: *mut u8) -> u64 {
+  PMEM.lock().unwrap().host_to_guest(haddr)
 }
 
 pub fn paddr_read(addr: u64, len: i32) -> u64 {
-  if in_pmem(addr) {
-    return pmem_read(addr, len)
-  }
-  out_of_bound(addr)
+  PMEM.lock().unwrap().read(addr, len)
 }
 
 pub fn paddr_write(addr: u64, len: i32, data: u64) {
-  if in_pmem(addr) {
-    return pmem_write(addr, len, data)
+  PMEM.lock().unwrap().write(addr, len, data)
+}
+
+pub fn paddr_try_read(addr: u64, len: i32) -> Option<u64> {
+  PMEM.lock().unwrap().try_read(addr, len)
+}
+
+pub fn paddr_try_write(addr: u64, len: i32, data: u64) -> bool {
+  PMEM.lock().unwrap().try_write(addr, len, data)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_small_pmem_last_word() {
+    let mut pmem = Pmem::new(0x1000);
+    pmem.write(MEM_BASE + 0xff8, 8, 0x1122334455667788);
+    assert_eq!(pmem.read(MEM_BASE + 0xff8, 8), 0x1122334455667788);
+  }
+
+  #[test]
+  #[should_panic(expected = "out of bound")]
+  fn test_small_pmem_past_end() {
+    let mut pmem = Pmem::new(0x1000);
+    pmem.read(MEM_BASE + 0x1000, 1);
+  }
+
+  #[test]
+  #[should_panic(expected = "out of bound")]
+  fn test_small_pmem_straddle_end() {
+    let mut pmem = Pmem::new(0x1000);
+    pmem.write(MEM_BASE + 0xffc, 8, 0);
+  }
+
+  #[test]
+  fn test_small_pmem_try_access() {
+    let mut pmem = Pmem::new(0x1000);
+    assert!(pmem.try_write(MEM_BASE + 0xff8, 8, 7));
+    assert_eq!(pmem.try_read(MEM_BASE + 0xff8, 8), Some(7));
+    assert!(!pmem.try_write(MEM_BASE + 0xffc, 8, 7));
+    assert_eq!(pmem.try_read(MEM_BASE - 1, 1), None);
+  }
+
+  #[test]
+  fn test_memory_regions() {
+    let regions = memory_regions();
+    assert_eq!(
+      regions,
+      vec![(MEM_BASE, MEM_BASE + pmem_size(), RegionKind::Ram)]
+    );
+  }
+
+  #[test]
+  fn test_extra_ram_region() {
+    let mut pmem = Pmem::new(0x1000);
+    pmem.add_region(0x90000000, 0x100).unwrap();
+    pmem.write(0x90000000 + 0xf8, 8, 0x1122334455667788);
+    assert_eq!(pmem.read(0x90000000 + 0xf8, 8), 0x1122334455667788);
+    assert_eq!(pmem.read(MEM_BASE + 0xf8, 8), 0);
+    assert_eq!(pmem.regions()[1], (0x90000000, 0x90000100, RegionKind::Ram));
+
+    assert!(pmem.add_region(0x900000f0, 0x100).is_err());
+    assert!(pmem.add_region(MEM_BASE + 0xff0, 0x100).is_err());
+    assert!(pmem.add_region(0xa0000000, 0).is_err());
+    assert!(pmem.add_region(0x200000000, MEM_SIZE_MAX + 1).is_err());
+  }
+
+  #[test]
+  #[should_panic(expected = "out of bound")]
+  fn test_extra_ram_region_past_end() {
+    let mut pmem = Pmem::new(0x1000);
+    pmem.add_region(0x90000000, 0x100).unwrap();
+    pmem.read(0x90000000 + 0xfc, 8);
+  }
+
+  #[test]
+  fn test_pmem_slice() {
+    let mut pmem = Pmem::new(0x1000);
+    pmem.write(MEM_BASE + 0x10, 4, 0x11223344);
+    assert_eq!(pmem.as_slice()[0x10..0x14], [0x44, 0x33, 0x22, 0x11]);
+  }
+
+  #[test]
+  fn test_pmem_bytes() {
+    let mut pmem = Pmem::new(0x1000);
+    pmem.add_region(0x90000000, 0x100).unwrap();
+    pmem
+      .bytes_mut(0x900000fc, 4)
+      .unwrap()
+      .copy_from_slice(&[1, 2, 3, 4]);
+    assert_eq!(pmem.read(0x900000fc, 4), 0x04030201);
+    assert_eq!(pmem.bytes_mut(MEM_BASE + 0xff0, 0x10).unwrap().len(), 0x10);
+    assert!(pmem.bytes_mut(MEM_BASE + 0xff0, 0x11).is_none());
+    assert!(pmem.bytes_mut(0x900000fc, 8).is_none());
+  }
+
+  #[test]
+  fn test_pmem_resize() {
+    let mut pmem = Pmem::new(0x1000);
+    pmem.resize(0x2000).unwrap();
+    pmem.write(MEM_BASE + 0x1ff8, 8, 1);
+    assert_eq!(pmem.size(), 0x2000);
+    assert_eq!(pmem.read(MEM_BASE + 0x1ff8, 8), 1);
+
+    assert!(pmem.resize(0).is_err());
+    assert!(pmem.resize(MEM_SIZE_MAX + 1).is_err());
+    assert!(pmem.resize(u64::MAX).is_err());
+    pmem.add_region(MEM_BASE + 0x3000, 0x100).unwrap();
+    assert!(pmem.resize(0x4000).is_err());
+    assert_eq!(pmem.size(), 0x2000);
   }
-  out_of_bound(addr)
 }
@@ -1,5 +1,6 @@
 use crate::memory::paddr::paddr_read;
 use crate::memory::paddr::paddr_write;
+use crate::memory::paddr::{paddr_try_read, paddr_try_write};
 
 pub fn vaddr_read(addr: u64, len: i32) -> u64 {
   paddr_read(addr, len)
@@ -7,4 +8,12 @@ pub fn vaddr_read(addr: u64, len: i32) -> u64 {
 
 pub fn vaddr_write(addr: u64, len: i32, data: u64) {
   paddr_write(addr, len, data)
-}
\ No newline at end of file
+}
+
+pub fn vaddr_try_read(addr: u64, len: i32) -> Option<u64> {
+  paddr_try_read(addr, len)
+}
+
+pub fn vaddr_try_write(addr: u64, len: i32, data: u64) -> bool {
+  paddr_try_write(addr, len, data)
+}
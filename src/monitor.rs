@@ -1,29 +1,43 @@
-pub mod sdb;
+pub mod config;
 pub mod expr;
+pub mod sdb;
 
 use crate::constants::*;
-use crate::log::init_log;
-use crate::memory::paddr::guest_to_host;
+use crate::cpu::disasm::disassemble;
+use crate::cpu::icache::ICacheConfig;
+use crate::cpu::isa_table;
+use crate::cpu::retire::diff_traces;
+use crate::log::{init_log, LogFormat};
+use crate::memory::paddr::{
+  add_ram_region, memory_regions, paddr_write, set_pmem_size,
+  with_paddr_bytes_mut,
+};
 use std::{
   io::{Read, Seek, SeekFrom},
   path::PathBuf,
 };
 
 use clap::Parser;
+use config::MachineConfig;
 use sdb::init_sdb;
+use sha2::{Digest, Sha256};
 
 /// A riscv64 monitor write in Rust.
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
-struct Args {
-  /// Run in batch mode
+pub struct Args {
+  /// Run in batch mode: run the img to the end without the sdb prompt
   #[arg(short, long, default_value = "false")]
-  batch: bool,
+  pub batch: bool,
 
   /// Log file
   #[arg(short, long, default_value = "tests/build/dummy-riscv64-nemu.log")]
   log: PathBuf,
 
+  /// Log output format
+  #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+  log_format: LogFormat,
+
   /// Diff file
   #[arg(short, long, default_value = "tests/build/dummy-riscv64-nemu.diff")]
   diff: PathBuf,
@@ -35,6 +49,131 @@ struct Args {
   /// Img file
   #[arg(short='f', long, default_value = "tests/build/dummy-riscv64-nemu.bin")]
   img: PathBuf,
+
+  /// Add a RAM region of SIZE bytes at BASE next to pmem, may be repeated
+  #[arg(long, value_name = "BASE:SIZE", value_parser = parse_region)]
+  ram: Vec<(u64, u64)>,
+
+  /// Copy FILE to physical address ADDR before running, may be repeated
+  #[arg(long, value_name = "ADDR:FILE", value_parser = parse_preload)]
+  preload: Vec<(u64, PathBuf)>,
+
+  /// Expected SHA-256 of the img file, the run is refused on a mismatch
+  #[arg(long, value_name = "HEX")]
+  img_sha256: Option<String>,
+
+  /// Machine description (TOML), the keys it sets override --mem-size and --reset-pc
+  #[arg(long)]
+  config: Option<PathBuf>,
+
+  /// Print a disassembly listing of this img file instead of running
+  #[arg(long)]
+  pub disasm: Option<PathBuf>,
+
+  /// Report the first retirement where two --retire-trace files differ
+  #[arg(long, num_args = 2, value_names = ["A", "B"])]
+  pub diff_traces: Option<Vec<PathBuf>>,
+
+  /// Write one line per retired instruction to this file
+  #[arg(long, conflicts_with = "count_only")]
+  pub retire_trace: Option<PathBuf>,
+
+  /// Dump the whole register file after every retire trace line, very verbose
+  #[arg(long, default_value = "false", requires = "retire_trace")]
+  pub trace_regs: bool,
+
+  /// Print the decode table as JSON instead of running
+  #[arg(long, default_value = "false")]
+  pub dump_isa: bool,
+
+  /// Load address used by --disasm
+  #[arg(long, default_value = "0x80000000", value_parser = parse_u64)]
+  base: u64,
+
+  /// Initial pc after reset, the img is loaded there
+  #[arg(long, default_value = "0x80000000", value_parser = parse_u64)]
+  pub reset_pc: u64,
+
+  /// Physical memory size in bytes
+  #[arg(long, default_value_t = MEM_SIZE)]
+  mem_size: u64,
+
+  /// Number of retired instructions to dump on a bad trap
+  #[arg(long, default_value = "0")]
+  pub history: usize,
+
+  /// Number of latest memory accesses kept for each s or c command, see `info m`
+  #[arg(long, default_value = "0")]
+  pub record_depth: usize,
+
+  /// Abort when a pc retires this many times with no register or memory change
+  #[arg(long, default_value = "0", conflicts_with = "count_only")]
+  pub progress_limit: u64,
+
+  /// Hash every retirement and print the result when the run ends
+  #[arg(long, default_value = "false", conflicts_with = "count_only")]
+  pub trace_hash: bool,
+
+  /// Only count instructions, skip all per-instruction tracing
+  #[arg(long, default_value = "false")]
+  pub count_only: bool,
+
+  /// Start from a machine state written by the sdb `save` command
+  #[arg(long, value_name = "FILE")]
+  pub load_state: Option<PathBuf>,
+
+  /// Model an instruction cache of SETS:WAYS:LINE_SIZE and count its hits
+  #[arg(long, value_parser = parse_icache)]
+  pub icache: Option<ICacheConfig>,
+}
+
+impl Args {
+  // only the keys present in the config override the flags
+  fn apply_config(&mut self, config: &MachineConfig) {
+    if let Some(mem_size) = config.mem_size {
+      self.mem_size = mem_size;
+    }
+    if let Some(reset_pc) = config.reset_pc {
+      self.reset_pc = reset_pc;
+    }
+  }
+}
+
+// accept both decimal and 0x-prefixed hexadecimal numbers
+fn parse_u64(s: &str) -> Result<u64, String> {
+  match s.strip_prefix("0x") {
+    Some(hex) => u64::from_str_radix(hex, 16),
+    None => s.parse::<u64>(),
+  }
+  .map_err(|e| e.to_string())
+}
+
+// ADDR:FILE, with ADDR in any form parse_u64 accepts
+fn parse_preload(s: &str) -> Result<(u64, PathBuf), String> {
+  let (addr, file) = s.split_once(':').ok_or("expected ADDR:FILE")?;
+  Ok((parse_u64(addr)?, PathBuf::from(file)))
+}
+
+// BASE:SIZE, both in any form parse_u64 accepts
+fn parse_region(s: &str) -> Result<(u64, u64), String> {
+  let (base, size) = s.split_once(':').ok_or("expected BASE:SIZE")?;
+  Ok((parse_u64(base)?, parse_u64(size)?))
+}
+
+// SETS:WAYS:LINE_SIZE, all non-zero
+fn parse_icache(s: &str) -> Result<ICacheConfig, String> {
+  let fields: Vec<u64> =
+    s.split(':').map(parse_u64).collect::<Result<_, _>>()?;
+  match fields[..] {
+    [sets, ways, line_size] if sets > 0 && ways > 0 && line_size > 0 => {
+      Ok(ICacheConfig {
+        sets,
+        ways: ways as usize,
+        line_size,
+      })
+    }
+    _ => Err("expected non-zero SETS:WAYS:LINE_SIZE".to_string()),
+  }
 }
 
 fn welcome() {
@@ -42,11 +181,32 @@ fn welcome() {
   log::info!("For help, type \"help\"");
 }
 
-// load img to memory(mmap)
-fn load_img(img_file: PathBuf) -> Result<usize, Box<dyn std::error::Error>> {
+// catch truncated or corrupt images before they run into garbage
+fn verify_sha256(data: &[u8], expected: &str) -> Result<(), String> {
+  let actual: String = Sha256::digest(data)
+    .iter()
+    .map(|b| format!("{:02x}", b))
+    .collect();
+  if actual != expected.to_lowercase() {
+    return Err(format!(
+      "img corrupt: sha256 is {}, expected {}",
+      actual, expected
+    ));
+  }
+  Ok(())
+}
+
+// load img to memory at `base`, the reset pc, returns the img size
+fn load_img(
+  img_file: PathBuf,
+  base: u64,
+  expected_sha256: Option<&str>,
+) -> Result<usize, Box<dyn std::error::Error>> {
   // open img file
   log::info!("img file:{}", img_file.to_string_lossy());
-  let mut file = std::fs::File::open(img_file)?;
+  let mut file = std::fs::File::open(&img_file).map_err(|err| {
+    format!("failed to open img {}: {}", img_file.display(), err)
+  })?;
 
   // get img size
   let size = file.seek(SeekFrom::End(0))?;
@@ -56,42 +216,305 @@ fn load_img(img_file: PathBuf) -> Result<usize, Box<dyn std::error::Error>> {
   file.seek(SeekFrom::Start(0))?;
   let mut buffer = vec![0; size as usize];
   file.read_exact(&mut buffer)?;
-
-  // copy img to memory
-  let dst = guest_to_host(RESET_VECTOR);
-  let src = buffer.as_ptr() as *const u8;
-  unsafe {
-    std::ptr::copy_nonoverlapping(src, dst, size as usize);
+  if let Some(expected) = expected_sha256 {
+    verify_sha256(&buffer, expected)?;
   }
 
+  // copy img to memory, it has to fit in one region
+  with_paddr_bytes_mut(base, buffer.len(), |mem| mem.copy_from_slice(&buffer))
+    .ok_or(format!(
+    "img [0x{:x}, 0x{:x}) is not mapped, check --reset-pc and the memory size",
+    base,
+    base + size
+  ))?;
+
   Ok(size as usize)
 }
 
-pub fn init_monitor() -> Result<(), Box<dyn std::error::Error>> {
-  let args = Args::parse();
+// place a data blob in mapped RAM, it must not overlap the img at [img.0, img.1)
+fn preload(
+  addr: u64,
+  bytes: &[u8],
+  img: (u64, u64),
+) -> Result<(), Box<dyn std::error::Error>> {
+  let end = addr
+    .checked_add(bytes.len() as u64)
+    .ok_or("preload range overflows")?;
+  if !memory_regions()
+    .iter()
+    .any(|&(start, stop, _)| addr >= start && end <= stop)
+  {
+    return Err(
+      format!("preload [0x{:x}, 0x{:x}) is not mapped", addr, end).into(),
+    );
+  }
+  if addr < img.1 && end > img.0 {
+    return Err(
+      format!("preload [0x{:x}, 0x{:x}) overlaps the img", addr, end).into(),
+    );
+  }
+  for (i, b) in bytes.iter().enumerate() {
+    paddr_write(addr + i as u64, 1, *b as u64);
+  }
+  Ok(())
+}
+
+fn isa_json() -> serde_json::Value {
+  isa_table()
+    .into_iter()
+    .map(|(mnemonic, pattern, category)| {
+      serde_json::json!({ "mnemonic": mnemonic, "pattern": pattern, "category": category })
+    })
+    .collect()
+}
 
-  init_log();
+fn disasm_listing(buffer: &[u8], base: u64) -> Vec<String> {
+  buffer
+    .chunks(4)
+    .enumerate()
+    .map(|(i, word)| {
+      let pc = base + i as u64 * 4;
+      let mut bytes = [0u8; 4];
+      bytes[..word.len()].copy_from_slice(word);
+      let inst = u32::from_le_bytes(bytes);
+      format!("{:8x}:\t{:08x}\t{}", pc, inst, disassemble(pc, inst))
+    })
+    .collect()
+}
+
+pub fn init_monitor() -> Result<Args, Box<dyn std::error::Error>> {
+  let mut args = Args::parse();
+  if let Some(path) = &args.config {
+    let config = MachineConfig::load(path)?;
+    args.apply_config(&config);
+  }
+
+  init_log(args.log_format);
+
+  if let Some(file) = &args.disasm {
+    for line in disasm_listing(&std::fs::read(file)?, args.base) {
+      println!("{}", line);
+    }
+    return Ok(args);
+  }
+
+  if args.dump_isa {
+    println!("{}", serde_json::to_string_pretty(&isa_json())?);
+    return Ok(args);
+  }
+
+  if let Some(files) = &args.diff_traces {
+    let (a, b) = (
+      std::fs::read_to_string(&files[0])?,
+      std::fs::read_to_string(&files[1])?,
+    );
+    match diff_traces(&a, &b) {
+      None => println!("traces match ({} retirements)", a.lines().count()),
+      Some(diff) => {
+        println!("traces diverge at retirement {}", diff.index);
+        for line in a
+          .lines()
+          .take(diff.index)
+          .skip(diff.index.saturating_sub(3))
+        {
+          println!("  {}", line);
+        }
+        println!("- {}", diff.a.unwrap_or("<end of trace>"));
+        println!("+ {}", diff.b.unwrap_or("<end of trace>"));
+      }
+    }
+    return Ok(args);
+  }
 
   init_sdb();
 
-  #[allow(unused_variables)]
-  let img_size = load_img(args.img).unwrap();
+  set_pmem_size(args.mem_size)?;
+  for &(base, size) in &args.ram {
+    add_ram_region(base, size)?;
+  }
+
+  let img_size =
+    load_img(args.img.clone(), args.reset_pc, args.img_sha256.as_deref())?;
+  let img = (args.reset_pc, args.reset_pc + img_size as u64);
+  for (addr, file) in &args.preload {
+    log::info!("preload {} at 0x{:x}", file.to_string_lossy(), addr);
+    preload(*addr, &std::fs::read(file)?, img)?;
+  }
 
   welcome();
 
-  Ok(())
+  Ok(args)
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::cpu::{BootConfig, Cpu};
+  use crate::memory::paddr::{paddr_read, pmem_size};
   use std::path::PathBuf;
 
   #[test]
   fn test_load_img() {
-    let file_path =
-      PathBuf::from("system-tests/cpu-tests/build/dummy-riscv64-hemu.bin");
-    let result = load_img(file_path).unwrap();
+    let file_path = PathBuf::from("tests/build/dummy-riscv64-nemu.bin");
+    let result = load_img(file_path, RESET_VECTOR, None).unwrap();
     println!("result:{}", result)
   }
+
+  #[test]
+  fn test_load_img_at_reset_pc() {
+    let file_path = PathBuf::from("tests/build/dummy-riscv64-nemu.bin");
+    let data = std::fs::read(&file_path).unwrap();
+    assert_eq!(
+      load_img(file_path.clone(), 0x80101d00, None).unwrap(),
+      data.len()
+    );
+    assert_eq!(
+      paddr_read(0x80101d00, 4),
+      u32::from_le_bytes(data[..4].try_into().unwrap()) as u64
+    );
+
+    let err = load_img(file_path.clone(), MEM_BASE - 0x1000, None).unwrap_err();
+    assert!(err.to_string().contains("is not mapped"), "{}", err);
+    assert!(load_img(file_path, MEM_BASE + pmem_size() - 4, None).is_err());
+  }
+
+  #[test]
+  fn test_load_img_checksum() {
+    let file_path = PathBuf::from("tests/build/dummy-riscv64-nemu.bin");
+    let data = std::fs::read(&file_path).unwrap();
+    let digest: String = Sha256::digest(&data)
+      .iter()
+      .map(|b| format!("{:02x}", b))
+      .collect();
+    assert!(load_img(
+      file_path.clone(),
+      RESET_VECTOR,
+      Some(&digest.to_uppercase())
+    )
+    .is_ok());
+
+    let err =
+      load_img(file_path, RESET_VECTOR, Some(&"0".repeat(64))).unwrap_err();
+    assert!(
+      err.to_string().starts_with("img corrupt: sha256 is "),
+      "{}",
+      err
+    );
+  }
+
+  #[test]
+  fn test_disasm_listing() {
+    let buffer = std::fs::read("tests/build/dummy-riscv64-nemu.bin").unwrap();
+    let listing = disasm_listing(&buffer, 0x80000000);
+    assert_eq!(listing[0], "80000000:\t00000413\taddi\ts0,zero,0");
+    assert_eq!(listing[3], "8000000c:\t00c000ef\tjal\tra,80000018");
+    assert_eq!(listing[9], "80000024:\t00113423\tsd\tra,8(sp)");
+    assert_eq!(listing[12], "80000030:\t00100073\tebreak");
+  }
+
+  #[test]
+  fn test_isa_json() {
+    let dump = isa_json().to_string();
+    let parsed: serde_json::Value = serde_json::from_str(&dump).unwrap();
+    let entries = parsed.as_array().unwrap();
+    let addi = entries.iter().find(|e| e["mnemonic"] == "addi").unwrap();
+    assert_eq!(addi["pattern"], "??????? ????? ????? 000 ????? 00100 11");
+    assert_eq!(addi["category"], "Immediate");
+    assert!(entries.iter().any(|e| e["mnemonic"] == "ebreak"));
+  }
+
+  // per-retirement consumers get nothing on the count-only path
+  #[test]
+  fn test_count_only_conflicts() {
+    assert!(Args::try_parse_from(["hemu", "--count-only"]).is_ok());
+    assert!(
+      Args::try_parse_from(["hemu", "--count-only", "--trace-hash"]).is_err()
+    );
+    assert!(Args::try_parse_from([
+      "hemu",
+      "--count-only",
+      "--retire-trace",
+      "t.log"
+    ])
+    .is_err());
+    assert!(Args::try_parse_from([
+      "hemu",
+      "--count-only",
+      "--progress-limit",
+      "100"
+    ])
+    .is_err());
+  }
+
+  // a key the config leaves out keeps the value from the command line
+  #[test]
+  fn test_apply_partial_config() {
+    let mut args =
+      Args::try_parse_from(["hemu", "--reset-pc", "0x80001000"]).unwrap();
+    let config = MachineConfig::from_toml("mem_size = 0x100000").unwrap();
+    args.apply_config(&config);
+    assert_eq!(args.mem_size, 0x100000);
+    assert_eq!(args.reset_pc, 0x80001000);
+  }
+
+  #[test]
+  fn test_parse_u64() {
+    assert_eq!(parse_u64("0x80000000"), Ok(0x80000000));
+    assert_eq!(parse_u64("4096"), Ok(4096));
+    assert!(parse_u64("0xgg").is_err());
+  }
+
+  #[test]
+  fn test_parse_preload() {
+    assert_eq!(
+      parse_preload("0x80100000:table.bin"),
+      Ok((0x80100000, PathBuf::from("table.bin")))
+    );
+    assert!(parse_preload("table.bin").is_err());
+  }
+
+  #[test]
+  fn test_parse_region() {
+    assert_eq!(parse_region("0x90000000:4096"), Ok((0x90000000, 4096)));
+    assert!(parse_region("0x90000000").is_err());
+  }
+
+  #[test]
+  fn test_parse_icache() {
+    assert_eq!(
+      parse_icache("64:4:0x40"),
+      Ok(ICacheConfig {
+        sets: 64,
+        ways: 4,
+        line_size: 64
+      })
+    );
+    assert!(parse_icache("64:4").is_err());
+    assert!(parse_icache("64:0:64").is_err());
+  }
+
+  #[test]
+  fn test_preload() {
+    let img = (RESET_VECTOR, RESET_VECTOR + 0x10);
+    assert!(preload(RESET_VECTOR + 0xc, &[0; 8], img).is_err());
+    assert!(preload(0x80100cfc, &[0; 8], (0x80100d00, 0x80100d10)).is_err());
+    assert!(preload(MEM_BASE - 1, &[0; 8], img).is_err());
+    assert!(preload(MEM_BASE + pmem_size() - 4, &[0; 8], img).is_err());
+    preload(0x80100d00, &0x1122334455667788u64.to_le_bytes(), img).unwrap();
+
+    // the guest sees the blob at the requested address
+    let mut boot = BootConfig::new();
+    boot.reset_pc = 0x80100d40;
+    let cpu = &mut Cpu::with_boot_config(boot);
+    let program: [u32; 3] = [
+      0x00000097, // auipc ra, 0
+      0xfc00b503, // ld a0, -64(ra)
+      0x00100073, // ebreak
+    ];
+    for (i, inst) in program.iter().enumerate() {
+      paddr_write(0x80100d40 + i as u64 * 4, 4, *inst as u64);
+    }
+    cpu.exec(usize::MAX);
+    assert_eq!(cpu.gpr[10], 0x1122334455667788);
+  }
 }
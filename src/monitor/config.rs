@@ -0,0 +1,55 @@
+use serde::Deserialize;
+use std::path::Path;
+
+/// Machine description loaded from a TOML file, keys left out keep the
+/// value given on the command line.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MachineConfig {
+  /// Physical memory size in bytes, mapped at MEM_BASE
+  pub mem_size: Option<u64>,
+
+  /// Initial pc after reset
+  pub reset_pc: Option<u64>,
+}
+
+impl MachineConfig {
+  pub fn from_toml(text: &str) -> Result<MachineConfig, toml::de::Error> {
+    toml::from_str(text)
+  }
+
+  pub fn load(
+    path: &Path,
+  ) -> Result<MachineConfig, Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(path).map_err(|err| {
+      format!("failed to read config {}: {}", path.display(), err)
+    })?;
+    Ok(MachineConfig::from_toml(&text)?)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_machine_config() {
+    let config =
+      MachineConfig::from_toml("mem_size = 0x100000\nreset_pc = 0x80001000\n")
+        .unwrap();
+    assert_eq!(config.mem_size, Some(0x100000));
+    assert_eq!(config.reset_pc, Some(0x80001000));
+  }
+
+  #[test]
+  fn test_machine_config_defaults() {
+    let config = MachineConfig::from_toml("").unwrap();
+    assert_eq!(config.mem_size, None);
+    assert_eq!(config.reset_pc, None);
+  }
+
+  #[test]
+  fn test_machine_config_unknown_field() {
+    assert!(MachineConfig::from_toml("dram_size = 1").is_err());
+  }
+}
@@ -50,15 +50,36 @@ impl RegConversion for String {
   }
 }
 
-pub fn expr(expression: String, cpu: &Cpu) -> u64 {
-  let expression = expression.hex().reg(cpu);
-  log::debug!("expr: {}", expression);
-  let result = eval(&expression)
-  .unwrap()
-  .as_f64()
-  .unwrap_or(114514.1919810);
-  unsafe{ 
-      result.to_int_unchecked::<u64>()
+// evaluate an sdb expression, a malformed one is reported instead of
+// panicking
+pub fn try_expr(expression: &str, cpu: &Cpu) -> Result<u64, String> {
+  if expression.trim().is_empty() {
+    return Err("empty expression".to_string());
+  }
+  for caps in Regex::new(r"0x([0-9a-fA-F]+)")
+    .unwrap()
+    .captures_iter(expression)
+  {
+    if u64::from_str_radix(&caps[1], 16).is_err() {
+      return Err(format!("0x{} does not fit in 64 bits", &caps[1]));
+    }
+  }
+  let re_gpr = Regex::new(r"^x(\d+)$").unwrap();
+  for caps in Regex::new(r"\$(\w+)").unwrap().captures_iter(expression) {
+    let known = &caps[1] == "pc"
+      || re_gpr
+        .captures(&caps[1])
+        .and_then(|gpr| atoi::<usize>(gpr[1].as_bytes()))
+        .is_some_and(|index| index < 32);
+    if !known {
+      return Err(format!("unknown register name: {}", &caps[1]));
+    }
+  }
+  let expression = expression.to_string().hex().reg(cpu);
+  let value = eval(&expression).map_err(|err| err.to_string())?;
+  match value.as_f64() {
+    Some(result) if result.is_finite() && result >= 0.0 => Ok(result as u64),
+    _ => Err(format!("{} is not an address", value)),
   }
 }
 
@@ -69,25 +90,41 @@ mod tests {
   #[test]
   // add sub mul div
   fn test_expr_asmd() {
-    assert_eq!(expr("1 + 2".to_string(), cpu), 3);
-    assert_eq!(expr("1 + 2 + 3".to_string(), cpu), 6);
-    assert_eq!(expr("1 + 2 * 3".to_string(), cpu), 7);
-    assert_eq!(expr("1 / 2 * 3".to_string(), cpu), 1);
-    assert_eq!(expr("1 / (2 * 3)".to_string(), cpu), 0);
-    assert_eq!(expr("0".to_string(), cpu), 0);
-    // if the expression is invalid, return 114514.0
-    assert_eq!(expr("1 / 0".to_string(), cpu), 114514);
+    let cpu = &Cpu::new();
+    assert_eq!(try_expr("1 + 2", cpu), Ok(3));
+    assert_eq!(try_expr("1 + 2 + 3", cpu), Ok(6));
+    assert_eq!(try_expr("1 + 2 * 3", cpu), Ok(7));
+    // fractions are truncated once, at the end
+    assert_eq!(try_expr("1 / 2 * 3", cpu), Ok(1));
+    assert_eq!(try_expr("1 / (2 * 3)", cpu), Ok(0));
+    assert_eq!(try_expr("0", cpu), Ok(0));
+    assert!(try_expr("1 / 0", cpu).is_err());
   }
 
   #[test]
   fn test_expr_hex() {
-    assert_eq!(expr("0x1".to_string(), cpu), 1);
-    assert_eq!(expr("0x10".to_string(), cpu), 16);
+    let cpu = &Cpu::new();
+    assert_eq!(try_expr("0x1", cpu), Ok(1));
+    assert_eq!(try_expr("0x10", cpu), Ok(16));
   }
 
   #[test]
   fn test_expr_reg() {
-    assert_eq!(expr("$pc".to_string(), cpu), cpu.pc);
-    assert_eq!(expr("$x1".to_string(), cpu), cpu.gpr[1]);
+    let cpu = &Cpu::new();
+    assert_eq!(try_expr("$pc", cpu), Ok(cpu.pc));
+    assert_eq!(try_expr("$x1", cpu), Ok(cpu.gpr[1]));
+  }
+
+  #[test]
+  fn test_try_expr() {
+    let cpu = &Cpu::new();
+    assert_eq!(try_expr("0x10 + 2", cpu), Ok(18));
+    assert_eq!(try_expr("$pc", cpu), Ok(cpu.pc));
+    assert!(try_expr("", cpu).is_err());
+    assert!(try_expr("$x32", cpu).is_err());
+    assert!(try_expr("$sp", cpu).is_err());
+    assert!(try_expr("abc", cpu).is_err());
+    assert!(try_expr("1 == 1", cpu).is_err());
+    assert!(try_expr("0x1ffffffffffffffff", cpu).is_err());
   }
 }
@@ -1,11 +1,13 @@
 use crate::cpu::{Cpu, CpuState};
 use crate::cpu::memory::read_data;
+use crate::memory::paddr::{memory_regions, paddr_read};
 use crate::monitor::expr;
 use atoi::atoi;
+use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
 struct CommandTable {
-  commands: [Command; 6],
+  commands: [Command; 10],
 }
 
 impl CommandTable {
@@ -15,15 +17,35 @@ impl CommandTable {
         Command::new("c", "Continue the execution", Command::r#continue),
         Command::new("q", "Exit hemu", Command::quit),
         Command::new("s", "Single step execution", Command::step),
-        Command::new("info", "Print register and watches info", Command::info),
+        Command::new("u", "Run until pc reaches ADDR: u ADDR", Command::until),
+        Command::new(
+          "info",
+          "Print register, watches and memory access info",
+          Command::info,
+        ),
         Command::new("p", "Calculate the expression", Command::expr),
         Command::new("x", "Scan memory", Command::scan),
+        Command::new(
+          "m",
+          "Hexdump physical memory: m ADDR LEN",
+          Command::hexdump,
+        ),
+        Command::new(
+          "r",
+          "Warm reset, memory and the loaded img are kept",
+          Command::reset,
+        ),
+        Command::new(
+          "save",
+          "Save the machine state to FILE, see --load-state",
+          Command::save,
+        ),
       ],
     }
   }
   #[allow(unused_variables)]
   fn help(&self, args: &str) -> i32 {
-    if args == "" {
+    if args.is_empty() {
       for i in 0..self.commands.len() {
         println!(
           "{} - {}",
@@ -82,17 +104,50 @@ impl Command {
     cpu.exec(atoi::<usize>(args.as_bytes()).unwrap_or(1))
   }
 
+  fn until(args: &str, cpu: &mut Cpu) -> i32 {
+    let addr = match expr::try_expr(args, cpu) {
+      Ok(addr) => addr,
+      Err(err) => {
+        println!("usage: u ADDR: {}", err);
+        return 0;
+      }
+    };
+    match cpu.step_until(|cpu| cpu.pc == addr, u64::MAX) {
+      Ok(steps) => println!("pc = 0x{:08x} after {} steps", addr, steps),
+      Err(err) => println!("{}", err),
+    }
+    0
+  }
+
   fn info(args: &str, cpu: &mut Cpu) -> i32 {
     if args == "r" {
       cpu.dump_registers();
     } else if args == "w" {
       cpu.dump_watches();
+    } else if args == "m" {
+      cpu.dump_mem_accesses();
+    } else if args == "mc" {
+      cpu.dump_coalesced_accesses();
     } else {
       println!("Unknown info '{}'", args);
     }
     0
   }
 
+  #[allow(unused_variables)]
+  fn reset(args: &str, cpu: &mut Cpu) -> i32 {
+    cpu.reset();
+    0
+  }
+
+  fn save(args: &str, cpu: &mut Cpu) -> i32 {
+    match cpu.save_state(std::path::Path::new(args.trim())) {
+      Ok(()) => println!("state saved to {}", args.trim()),
+      Err(err) => println!("failed to save state: {}", err),
+    }
+    0
+  }
+
   #[allow(unused_variables)]
   fn quit(args: &str, cpu: &mut Cpu) -> i32 {
     cpu.state = CpuState::Quit;
@@ -100,16 +155,30 @@ impl Command {
   }
 
   fn expr(args: &str, cpu: &mut Cpu) -> i32 {
-    println!("0x{:08x}", expr::expr(args.to_string(), cpu));
+    match expr::try_expr(args, cpu) {
+      Ok(value) => println!("0x{:08x}", value),
+      Err(err) => println!("usage: p EXPR: {}", err),
+    }
     0
   }
 
-  #[allow(unused_variables)]
   fn scan(args: &str, cpu: &mut Cpu) -> i32 {
     let mut parts = args.splitn(2, ' ');
     let input_size = parts.next().unwrap_or("");
-    let input_addr = expr::expr(parts.next().unwrap_or("").to_string(), cpu);
-    (0..atoi::<usize>(input_size.as_bytes()).unwrap_or(1)).for_each(|i| {
+    let input_addr = match expr::try_expr(parts.next().unwrap_or(""), cpu) {
+      Ok(addr) => addr,
+      Err(err) => {
+        println!("usage: x N ADDR: {}", err);
+        return 0;
+      }
+    };
+    let count = atoi::<usize>(input_size.as_bytes()).unwrap_or(1);
+    let len = (count as u64).checked_mul(4);
+    if !len.is_some_and(|len| mapped(input_addr, len)) {
+      println!("0x{:08x}+{} words is not mapped", input_addr, count);
+      return 0;
+    }
+    (0..count).for_each(|i| {
       if i % 4 == 0 {
         print!("0x{:08x}: ", input_addr + i as u64 * 4);
       }
@@ -121,43 +190,220 @@ impl Command {
     println!(); // buffer flush
     0
   }
+  fn hexdump(args: &str, cpu: &mut Cpu) -> i32 {
+    let (input_addr, len) = match hexdump_args(args, cpu) {
+      Ok(parsed) => parsed,
+      Err(err) => {
+        println!("usage: m ADDR [LEN]: {}", err);
+        return 0;
+      }
+    };
+    if !mapped(input_addr, len) {
+      println!("0x{:08x}+{} is not mapped", input_addr, len);
+      return 0;
+    }
+    for line in hexdump(input_addr, len) {
+      println!("{}", line);
+    }
+    0
+  }
+}
+
+// `m ADDR [LEN]`, ADDR is the first token and LEN defaults to 16
+fn hexdump_args(args: &str, cpu: &Cpu) -> Result<(u64, u64), String> {
+  let mut parts = args.trim().splitn(2, ' ');
+  let addr = expr::try_expr(parts.next().unwrap_or(""), cpu)?;
+  let len = match parts.next() {
+    Some(len) => expr::try_expr(len, cpu)?,
+    None => 16,
+  };
+  Ok((addr, len))
+}
+
+// the whole range [addr, addr + len) lies in one memory region
+fn mapped(addr: u64, len: u64) -> bool {
+  memory_regions().iter().any(|&(start, end, _)| {
+    addr >= start && addr.checked_add(len).is_some_and(|last| last <= end)
+  })
+}
+
+// classic 16 bytes per line hexdump of physical memory
+fn hexdump(addr: u64, len: u64) -> Vec<String> {
+  (addr..addr + len)
+    .step_by(16)
+    .map(|line_addr| {
+      let bytes: Vec<u8> = (line_addr..(line_addr + 16).min(addr + len))
+        .map(|a| paddr_read(a, 1) as u8)
+        .collect();
+      let hex: String = bytes.iter().map(|b| format!("{:02x} ", b)).collect();
+      let ascii: String = bytes
+        .iter()
+        .map(|&b| {
+          if (0x20..0x7f).contains(&b) {
+            b as char
+          } else {
+            '.'
+          }
+        })
+        .collect();
+      format!("0x{:08x}: {:<48}|{}|", line_addr, hex, ascii)
+    })
+    .collect()
+}
+
+// what the main loop should do with one readline result
+#[derive(Debug)]
+enum Input {
+  Line(String),
+  Skip,
+  Quit,
+  Error(ReadlineError),
+}
+
+fn classify_input(readline: Result<String, ReadlineError>) -> Input {
+  match readline {
+    Ok(line) if line.trim().is_empty() => Input::Skip,
+    Ok(line) => Input::Line(line.trim().to_string()),
+    // Ctrl-C drops the current line, Ctrl-D leaves like `q`
+    Err(ReadlineError::Interrupted) => Input::Skip,
+    Err(ReadlineError::Eof) => Input::Quit,
+    Err(err) => Input::Error(err),
+  }
 }
 
 pub fn sdb_mainloop(cpu: &mut Cpu) {
   let cmd_table = CommandTable::new();
   let mut rl = Editor::<()>::new();
   'out: loop {
-    let readline = rl.readline("(hemu) ");
-    match readline {
-      Ok(line) => {
-        rl.add_history_entry(line.trim());
-
-        let mut parts = line.trim().splitn(2, ' ');
-        let input_cmd = parts.next().unwrap_or("");
-        let input_args = parts.next().unwrap_or("");
+    let line = match classify_input(rl.readline("(hemu) ")) {
+      Input::Line(line) => line,
+      Input::Skip => continue,
+      Input::Quit => {
+        log::info!("Quit hemu");
+        break;
+      }
+      Input::Error(err) => {
+        log::error!("readline failed: {}", err);
+        break;
+      }
+    };
+    rl.add_history_entry(line.as_str());
 
-        if input_cmd == "" {
-          continue;
-        }
+    let mut parts = line.splitn(2, ' ');
+    let input_cmd = parts.next().unwrap_or("");
+    let input_args = parts.next().unwrap_or("");
 
-        for cmd in cmd_table.commands.iter() {
-          if input_cmd == cmd.name {
-            if cmd.handle(input_args, cpu) < 0 {
-              log::info!("Quit hemu");
-              break 'out;
-            }
-            continue 'out;
-          }
+    for cmd in cmd_table.commands.iter() {
+      if input_cmd == cmd.name {
+        if cmd.handle(input_args, cpu) < 0 {
+          log::info!("Quit hemu");
+          break 'out;
         }
-
-        cmd_table.help("");
-      }
-      Err(_) => {
-        println!("Error!");
-        break;
+        continue 'out;
       }
     }
+
+    cmd_table.help("");
   }
 }
 
 pub fn init_sdb() {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::memory::paddr::paddr_write;
+
+  #[test]
+  fn test_hexdump() {
+    for (i, b) in b"hemu\x00\x01\x7f\xffABCDEFGH0123".iter().enumerate() {
+      paddr_write(0x80100900 + i as u64, 1, *b as u64);
+    }
+
+    let lines = hexdump(0x80100900, 20);
+    assert_eq!(lines, vec![
+      "0x80100900: 68 65 6d 75 00 01 7f ff 41 42 43 44 45 46 47 48 |hemu....ABCDEFGH|",
+      "0x80100910: 30 31 32 33                                     |0123|",
+    ]);
+  }
+
+  #[test]
+  fn test_hexdump_args() {
+    let cpu = &mut Cpu::new();
+    assert_eq!(hexdump_args("0x80000000", cpu), Ok((0x80000000, 16)));
+    assert_eq!(hexdump_args(" 0x80000000 0x20 ", cpu), Ok((0x80000000, 32)));
+    assert!(hexdump_args("", cpu).is_err());
+    assert!(hexdump_args("$sp 16", cpu).is_err());
+    // a single argument is the address, not the length
+    assert_eq!(Command::hexdump("0x80000000", cpu), 0);
+    assert_eq!(Command::hexdump("", cpu), 0);
+  }
+
+  // bad input and unmapped addresses are reported, not a host panic
+  #[test]
+  fn test_scan_and_expr_bad_input() {
+    let cpu = &mut Cpu::new();
+    assert_eq!(Command::scan("4 0x10", cpu), 0);
+    assert_eq!(Command::scan("4 $sp", cpu), 0);
+    assert_eq!(Command::scan("4", cpu), 0);
+    assert_eq!(Command::scan("4 0x80000000", cpu), 0);
+    assert_eq!(Command::expr("abc", cpu), 0);
+    assert_eq!(Command::expr("$x99", cpu), 0);
+  }
+
+  #[test]
+  fn test_until() {
+    for (i, inst) in [
+      0x00100513, // addi a0, zero, 1
+      0x00150513, // addi a0, a0, 1
+      0x00100073, // ebreak
+    ]
+    .iter()
+    .enumerate()
+    {
+      paddr_write(0x80102200 + i as u64 * 4, 4, *inst);
+    }
+    let cpu = &mut Cpu::new();
+    cpu.pc = 0x80102200;
+    assert_eq!(Command::until("0x80102208", cpu), 0);
+    assert_eq!((cpu.pc, cpu.gpr[10]), (0x80102208, 2));
+    assert!(cpu.state == CpuState::Running);
+
+    // the ebreak stops the run before pc gets there
+    assert_eq!(Command::until("0x80102300", cpu), 0);
+    assert!(cpu.state == CpuState::Ended);
+    assert_eq!(Command::until("$sp +", cpu), 0);
+  }
+
+  #[test]
+  fn test_mapped() {
+    let (start, end, _) = memory_regions()[0];
+    assert!(mapped(start, 16));
+    assert!(mapped(end - 16, 16));
+    assert!(!mapped(end - 8, 16));
+    assert!(!mapped(start - 1, 1));
+    assert!(!mapped(u64::MAX, 2));
+  }
+
+  #[test]
+  fn test_classify_input() {
+    assert!(matches!(
+      classify_input(Err(ReadlineError::Eof)),
+      Input::Quit
+    ));
+    assert!(matches!(
+      classify_input(Err(ReadlineError::Interrupted)),
+      Input::Skip
+    ));
+    assert!(matches!(
+      classify_input(Ok(" \t ".to_string())),
+      Input::Skip
+    ));
+    assert!(
+      matches!(classify_input(Ok(" s 2 ".to_string())), Input::Line(l) if l == "s 2")
+    );
+    let err =
+      ReadlineError::Io(std::io::Error::from(std::io::ErrorKind::BrokenPipe));
+    assert!(matches!(classify_input(Err(err)), Input::Error(_)));
+  }
+}